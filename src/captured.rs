@@ -0,0 +1,237 @@
+use std::path::Path;
+
+use ntex::util::Bytes;
+use sha2::{Digest, Sha256};
+use tokio::fs::File;
+use tokio::io::AsyncWriteExt;
+
+use crate::checksum::{hex, Hasher};
+use crate::file::FileInfo;
+use crate::metadata::strip_metadata;
+use crate::pipeline::{variant_path, ImagePipeline, ImageProcessor, ImageVariant};
+use crate::result::{MultipartError, MultipartResult};
+use crate::sanitize::with_extension;
+use crate::scan::{ChunkReader, ScanVerdict, Scanner};
+use crate::storage::StorageBackend;
+
+#[derive(Debug, Default, Clone)]
+pub struct CapturedFile {
+    pub(crate) file: FileInfo,
+    pub(crate) bytes: Vec<Bytes>,
+    /// Encoded [`FileInfo::variants`], in the same order
+    pub(crate) variants: Vec<Bytes>,
+}
+
+impl CapturedFile {
+    pub(crate) fn new(file: FileInfo, bytes: Vec<Bytes>) -> Self {
+        Self {
+            file,
+            bytes,
+            variants: vec![],
+        }
+    }
+
+    /// Writes the file to `path` and any derived variants next to it,
+    /// e.g. `photo_thumb.webp` beside `photo.jpg`
+    pub async fn save<P: AsRef<Path>>(&self, path: &P) -> MultipartResult<()> {
+        write_file(path.as_ref(), &self.bytes).await?;
+
+        for (variant, bytes) in self.file.variants.iter().zip(&self.variants) {
+            let path = variant_path(path.as_ref(), variant);
+            write_file(&path, std::slice::from_ref(bytes)).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Checks the captured bytes with `scanner`, failing with
+    /// [`MultipartError::Infected`](crate::MultipartError::Infected) when malware is found
+    pub async fn scan<S: Scanner>(&self, scanner: &S) -> MultipartResult<()> {
+        match scanner
+            .scan(&self.file, ChunkReader::new(&self.bytes))
+            .await?
+        {
+            ScanVerdict::Clean => Ok(()),
+            ScanVerdict::Infected(signature) => Err(MultipartError::Infected { signature }),
+        }
+    }
+
+    /// Runs `pipeline` on the captured image, replacing its bytes with the processed
+    /// image and recording derived images in [`FileInfo::variants`]
+    pub fn process<P: ImageProcessor>(
+        &mut self,
+        pipeline: &ImagePipeline,
+        processor: &P,
+    ) -> MultipartResult<()> {
+        pipeline.run(self, processor)
+    }
+
+    /// Removes EXIF, XMP and GPS metadata from a JPEG, PNG or WebP image in place,
+    /// without re-encoding it; checksums are recomputed over the stripped bytes
+    pub fn strip_metadata(&mut self) -> MultipartResult<()> {
+        let bytes = self.bytes.concat();
+        let stripped = strip_metadata(&bytes)?;
+        if stripped.len() == bytes.len() {
+            return Ok(());
+        }
+
+        let mut hasher = Hasher::new(&self.file.checksums.algorithms());
+        hasher.update(&stripped);
+        self.file.checksums = hasher.finish();
+        self.file.size = stripped.len();
+        self.bytes = vec![Bytes::from(stripped)];
+        Ok(())
+    }
+
+    /// A variant derived by [`CapturedFile::process`] together with its encoded bytes
+    pub fn variant(&self, name: &str) -> Option<(&ImageVariant, &[u8])> {
+        self.file
+            .variants
+            .iter()
+            .zip(&self.variants)
+            .find(|(variant, _)| variant.name == name)
+            .map(|(variant, bytes)| (variant, bytes.as_ref()))
+    }
+
+    /// Writes the captured bytes to `storage` under `key`
+    pub async fn store<S: StorageBackend>(&self, storage: &S, key: &str) -> MultipartResult<()> {
+        storage.put(key, &self.file, &self.bytes).await
+    }
+
+    /// Writes the bytes to `storage` under [`CapturedFile::content_key`], skipping the
+    /// write when an identical object is already stored; returns the key.
+    ///
+    /// Concurrent uploads of the same content may both write; as the key names the
+    /// content, either write wins and a failed one is fine once the object exists.
+    pub async fn store_deduplicated<S: StorageBackend>(
+        &self,
+        storage: &S,
+    ) -> MultipartResult<String> {
+        let key = self.content_key();
+        if storage.exists(&key).await? {
+            return Ok(key);
+        }
+
+        match storage.put(&key, &self.file, &self.bytes).await {
+            Ok(()) => Ok(key),
+            Err(err) => match storage.exists(&key).await {
+                Ok(true) => Ok(key),
+                _ => Err(err),
+            },
+        }
+    }
+
+    pub fn file(&self) -> &FileInfo {
+        &self.file
+    }
+
+    pub fn bytes(&self) -> &[Bytes] {
+        &self.bytes
+    }
+
+    /// Hex encoded SHA-256 of the content, reusing the digest computed during capture
+    pub fn content_hash(&self) -> String {
+        if let Some(sha256) = &self.file.checksums.sha256 {
+            return sha256.clone();
+        }
+
+        let mut hasher = Sha256::new();
+        for byte in &self.bytes {
+            hasher.update(byte);
+        }
+        hex(&hasher.finalize())
+    }
+
+    /// Content addressed key sharded by the leading hash bytes, as in `ab/cd/abcdef…`
+    pub fn content_key(&self) -> String {
+        let hash = self.content_hash();
+        format!("{}/{}/{}", &hash[..2], &hash[2..4], hash)
+    }
+
+    /// Storage name derived from the SHA-256 of the content, carrying the file's
+    /// [inferred extension](FileInfo::inferred_extension); identical uploads always
+    /// map to the same name
+    pub fn hashed_name(&self) -> String {
+        let extension = self.file.inferred_extension();
+        with_extension(self.content_hash(), extension.as_deref())
+    }
+}
+
+async fn write_file(path: &Path, chunks: &[Bytes]) -> MultipartResult<()> {
+    let mut file = File::create(path).await?;
+
+    for chunk in chunks {
+        file.write_all(chunk).await?;
+    }
+
+    file.flush().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{LocalStorage, MemoryStorage};
+
+    #[tokio::test]
+    async fn test_store_deduplicated() {
+        let storage = MemoryStorage::new();
+        let first = CapturedFile::new(FileInfo::default(), vec![Bytes::from_static(b"abc")]);
+        let second = CapturedFile::new(
+            FileInfo {
+                name: "copy.txt".to_string(),
+                ..FileInfo::default()
+            },
+            vec![Bytes::from_static(b"a"), Bytes::from_static(b"bc")],
+        );
+
+        let key = first.store_deduplicated(&storage).await.unwrap();
+        assert_eq!(
+            key,
+            "ba/78/ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(second.store_deduplicated(&storage).await.unwrap(), key);
+        assert_eq!(storage.keys(), vec![key]);
+    }
+
+    #[tokio::test]
+    async fn test_store_deduplicated_concurrently() {
+        let root = std::env::temp_dir().join("medullah-dedup-concurrent");
+        let _ = std::fs::remove_dir_all(&root);
+        let storage = LocalStorage::new(&root);
+
+        let file = CapturedFile::new(FileInfo::default(), vec![Bytes::from_static(b"abc")]);
+        let (first, second) = futures::join!(
+            file.store_deduplicated(&storage),
+            file.store_deduplicated(&storage)
+        );
+
+        let key = first.unwrap();
+        assert_eq!(second.unwrap(), key);
+        assert_eq!(std::fs::read(storage.path(&key).unwrap()).unwrap(), b"abc");
+        assert_eq!(
+            std::fs::read_dir(storage.path(&key).unwrap().parent().unwrap())
+                .unwrap()
+                .count(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_hashed_name() {
+        let hashed = |name: &str, detected: Option<&str>| {
+            let mut info = FileInfo::new("file".into(), name.into(), "".into());
+            info.detected_content_type = detected.map(str::to_string);
+            CapturedFile::new(info, vec![Bytes::from_static(b"abc")]).hashed_name()
+        };
+
+        let hash = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+        assert_eq!(hashed("../Report.PDF", None), format!("{}.pdf", hash));
+        assert_eq!(hashed("site.tar.gz", None), format!("{}.tar.gz", hash));
+        assert_eq!(hashed("README", Some("image/png")), format!("{}.png", hash));
+        assert_eq!(
+            hashed("shell.php", Some("image/png")),
+            format!("{}.png", hash)
+        );
+    }
+}
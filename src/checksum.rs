@@ -0,0 +1,137 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use md5::Md5;
+use sha2::{Digest, Sha256};
+
+/// Digest algorithms that can be computed while a field is being captured
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChecksumAlgorithm {
+    Sha256,
+    Md5,
+    Crc32c,
+}
+
+/// Lowercase hex digests computed during capture
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Checksums {
+    pub sha256: Option<String>,
+    pub md5: Option<String>,
+    pub crc32c: Option<String>,
+}
+
+impl Checksums {
+    pub fn get(&self, algorithm: ChecksumAlgorithm) -> Option<&str> {
+        match algorithm {
+            ChecksumAlgorithm::Sha256 => self.sha256.as_deref(),
+            ChecksumAlgorithm::Md5 => self.md5.as_deref(),
+            ChecksumAlgorithm::Crc32c => self.crc32c.as_deref(),
+        }
+    }
+
+    /// Algorithms that have a digest here
+    pub(crate) fn algorithms(&self) -> Vec<ChecksumAlgorithm> {
+        [
+            ChecksumAlgorithm::Sha256,
+            ChecksumAlgorithm::Md5,
+            ChecksumAlgorithm::Crc32c,
+        ]
+        .into_iter()
+        .filter(|algorithm| self.get(*algorithm).is_some())
+        .collect()
+    }
+}
+
+/// Incrementally hashes chunks with every requested algorithm
+#[derive(Default)]
+pub(crate) struct Hasher {
+    sha256: Option<Sha256>,
+    md5: Option<Md5>,
+    crc32c: Option<u32>,
+}
+
+impl Hasher {
+    pub(crate) fn new<'a>(algorithms: impl IntoIterator<Item = &'a ChecksumAlgorithm>) -> Self {
+        let mut hasher = Self::default();
+        for algorithm in algorithms {
+            match algorithm {
+                ChecksumAlgorithm::Sha256 => hasher.sha256 = Some(Sha256::new()),
+                ChecksumAlgorithm::Md5 => hasher.md5 = Some(Md5::new()),
+                ChecksumAlgorithm::Crc32c => hasher.crc32c = Some(0),
+            }
+        }
+        hasher
+    }
+
+    pub(crate) fn update(&mut self, data: &[u8]) {
+        if let Some(sha256) = self.sha256.as_mut() {
+            sha256.update(data);
+        }
+
+        if let Some(md5) = self.md5.as_mut() {
+            md5.update(data);
+        }
+
+        if let Some(crc) = self.crc32c.as_mut() {
+            *crc = crc32c::crc32c_append(*crc, data);
+        }
+    }
+
+    pub(crate) fn finish(self) -> Checksums {
+        Checksums {
+            sha256: self.sha256.map(|h| hex(&h.finalize())),
+            md5: self.md5.map(|h| hex(&h.finalize())),
+            crc32c: self.crc32c.map(|crc| format!("{:08x}", crc)),
+        }
+    }
+}
+
+/// Compares a client supplied digest, hex or base64 encoded, with a computed hex digest
+pub(crate) fn digest_matches(expected: &str, actual_hex: &str) -> bool {
+    let expected = expected.trim();
+    if expected.eq_ignore_ascii_case(actual_hex) {
+        return true;
+    }
+
+    STANDARD
+        .decode(expected)
+        .is_ok_and(|bytes| hex(&bytes) == actual_hex)
+}
+
+pub(crate) fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_incremental_checksums() {
+        let mut hasher = Hasher::new(&[
+            ChecksumAlgorithm::Sha256,
+            ChecksumAlgorithm::Md5,
+            ChecksumAlgorithm::Crc32c,
+        ]);
+        hasher.update(b"ab");
+        hasher.update(b"c");
+        let checksums = hasher.finish();
+
+        assert_eq!(
+            checksums.sha256.as_deref(),
+            Some("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad")
+        );
+        assert_eq!(
+            checksums.md5.as_deref(),
+            Some("900150983cd24fb0d6963f7d28e17f72")
+        );
+        assert_eq!(checksums.crc32c.as_deref(), Some("364b3fb7"));
+    }
+
+    #[test]
+    fn test_digest_matches() {
+        let md5 = "900150983cd24fb0d6963f7d28e17f72";
+        assert!(digest_matches("900150983CD24FB0D6963F7D28E17F72", md5));
+        assert!(digest_matches("kAFQmDzST7DWlj99KOF/cg==", md5));
+        assert!(!digest_matches("not-a-digest", md5));
+    }
+}
@@ -0,0 +1,470 @@
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tokio::fs::{self, File};
+use tokio::io::AsyncWriteExt;
+
+use crate::file::FileInfo;
+use crate::result::MultipartError::ValidationError;
+use crate::result::MultipartValidationError::UpperSizeError;
+use crate::result::{FormError, MultipartError, MultipartResult};
+use crate::streamed::StreamedFile;
+use crate::text::TextFields;
+use crate::uploader::{UploadData, Uploader};
+
+/// Reassembles files that clients such as Dropzone, Resumable.js or FilePond split
+/// into numbered chunks, each sent as its own multipart request.
+///
+/// Chunks are staged in a directory per upload id below `root`; once every chunk
+/// arrived they are joined, in index order, into a single [`StreamedFile`] named
+/// after the first chunk. Directories of uploads that were never completed remain
+/// until [`ChunkAssembler::abort`] or [`ChunkAssembler::sweep`] removes them.
+#[derive(Debug, Clone)]
+pub struct ChunkAssembler {
+    root: PathBuf,
+    id_field: String,
+    index_field: String,
+    total_field: String,
+    filename_field: Option<String>,
+    first_index: usize,
+    max_chunks: Option<usize>,
+    max_total_size: Option<usize>,
+    sequential: bool,
+}
+
+/// Outcome of accepting a chunk
+#[derive(Debug)]
+pub enum ChunkStatus {
+    /// More chunks are expected
+    Pending { received: usize, total: usize },
+    /// The last chunk arrived and the file was assembled
+    Complete(Box<StreamedFile>),
+}
+
+impl ChunkAssembler {
+    /// Stages chunks below `root`, reading the `uploadId`, `chunkIndex` and `totalChunks` fields
+    pub fn new<P: AsRef<Path>>(root: P) -> Self {
+        Self {
+            root: root.as_ref().to_path_buf(),
+            id_field: "uploadId".to_string(),
+            index_field: "chunkIndex".to_string(),
+            total_field: "totalChunks".to_string(),
+            filename_field: None,
+            first_index: 0,
+            max_chunks: None,
+            max_total_size: None,
+            sequential: false,
+        }
+    }
+
+    /// Names of the text fields carrying the upload id, chunk index and chunk count,
+    /// e.g. `dzuuid`, `dzchunkindex` and `dztotalchunkcount` for Dropzone
+    pub fn fields(mut self, id: &str, index: &str, total: &str) -> Self {
+        self.id_field = id.to_string();
+        self.index_field = index.to_string();
+        self.total_field = total.to_string();
+        self
+    }
+
+    /// Text field holding the name of the whole file, for clients that send every
+    /// chunk under a placeholder name such as Dropzone's `blob`
+    pub fn filename_field(mut self, name: &str) -> Self {
+        self.filename_field = Some(name.to_string());
+        self
+    }
+
+    /// Index of the first chunk, `1` for Resumable.js; defaults to `0`
+    pub fn first_index(mut self, first_index: usize) -> Self {
+        self.first_index = first_index;
+        self
+    }
+
+    pub fn max_chunks(mut self, max_chunks: usize) -> Self {
+        self.max_chunks = Some(max_chunks);
+        self
+    }
+
+    /// Maximum size of the assembled file
+    pub fn max_total_size(mut self, max_total_size: usize) -> Self {
+        self.max_total_size = Some(max_total_size);
+        self
+    }
+
+    /// Rejects chunks sent ahead of the next expected one; a chunk may still be resent
+    pub fn sequential(mut self, sequential: bool) -> Self {
+        self.sequential = sequential;
+        self
+    }
+
+    /// Captures the chunk in `ud.field` and stages it, assembling the file once it was
+    /// the last one missing. The id, index and count fields must precede the chunk.
+    pub async fn accept(
+        &self,
+        uploader: &mut Uploader,
+        ud: UploadData<'_>,
+    ) -> MultipartResult<ChunkStatus> {
+        let chunk = uploader.capture_to_temp(ud).await?;
+        let fields = uploader.text_fields();
+
+        let id: String = self.required(fields, &self.id_field)?;
+        let index: usize = self.required(fields, &self.index_field)?;
+        let total: usize = self.required(fields, &self.total_field)?;
+
+        if !is_upload_id(&id) {
+            return Err(invalid(&self.id_field, "invalid upload id"));
+        }
+
+        if total == 0 || self.max_chunks.is_some_and(|max| total > max) {
+            return Err(invalid(&self.total_field, "invalid number of chunks"));
+        }
+
+        let index = match index.checked_sub(self.first_index) {
+            Some(index) if index < total => index,
+            _ => return Err(invalid(&self.index_field, "chunk index out of range")),
+        };
+
+        let staging = self.root.join(&id);
+        fs::create_dir_all(&staging).await?;
+        self.check_total(&staging, total).await?;
+
+        if self.sequential {
+            let (staged, _) = self.tally(&staging, total).await?;
+            let next = staged.iter().position(|staged| !staged).unwrap_or(total);
+            if index > next {
+                return Err(invalid(&self.index_field, "chunk sent out of order"));
+            }
+        }
+
+        if index == 0 {
+            self.record_first(&staging, chunk.file()).await?;
+        }
+
+        let mut info = chunk.file().clone();
+        chunk.persist(&part_path(&staging, index)).await?;
+
+        let (staged, size) = self.tally(&staging, total).await?;
+        let received = staged.iter().filter(|staged| **staged).count();
+        if self.max_total_size.is_some_and(|max| size > max) {
+            let _ = fs::remove_dir_all(&staging).await;
+            return Err(ValidationError(UpperSizeError));
+        }
+
+        if received < total {
+            return Ok(ChunkStatus::Pending { received, total });
+        }
+
+        // claiming the staging directory makes sure concurrent last chunks assemble only once
+        let assembling = self.root.join(format!("{}.assembling", id));
+        match fs::rename(&staging, &assembling).await {
+            Err(err) if err.kind() == ErrorKind::NotFound => {
+                return Ok(ChunkStatus::Pending { received, total });
+            }
+            result => result?,
+        }
+
+        // the chunks are gone afterwards either way, a failed assembly can't be retried
+        let assembled = self.assemble(&assembling, total).await;
+        let _ = fs::remove_dir_all(&assembling).await;
+        let (mut assembled, first) = assembled?;
+
+        if let Some(first) = first {
+            info = FileInfo::new(info.field, first.name, first.content_type);
+        }
+
+        let filename = self.filename_field.as_deref().and_then(|f| fields.get(f));
+        if let Some(name) = filename.filter(|name| !name.is_empty()) {
+            info = FileInfo::new(info.field, name.to_string(), info.content_type);
+        }
+
+        assembled.file = info;
+        assembled.file.size = size;
+        Ok(ChunkStatus::Complete(Box::new(assembled)))
+    }
+
+    /// Discards every chunk staged for the upload `id`, including an assembly left
+    /// behind by a failed request
+    pub async fn abort(&self, id: &str) -> MultipartResult<()> {
+        if !is_upload_id(id) {
+            return Ok(());
+        }
+
+        remove_staging(&self.root.join(id)).await?;
+        remove_staging(&self.root.join(format!("{}.assembling", id))).await?;
+        Ok(())
+    }
+
+    /// Removes the chunks of uploads that received none for `max_age`, such as those
+    /// abandoned by their clients, returning how many uploads were removed.
+    ///
+    /// Nothing expires on its own; call this periodically, e.g. from a timer task.
+    pub async fn sweep(&self, max_age: Duration) -> MultipartResult<usize> {
+        let mut entries = match fs::read_dir(&self.root).await {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(0),
+            Err(err) => return Err(err.into()),
+        };
+
+        let mut removed = 0;
+        while let Some(entry) = entries.next_entry().await? {
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            let id = name.strip_suffix(".assembling").unwrap_or(&name);
+            if !is_upload_id(id) || !entry.file_type().await?.is_dir() {
+                continue;
+            }
+
+            // adding a chunk adds an entry, which updates the directory's mtime
+            let modified = entry.metadata().await?.modified()?;
+            if modified.elapsed().is_ok_and(|age| age >= max_age) {
+                remove_staging(&entry.path()).await?;
+                removed += 1;
+            }
+        }
+
+        Ok(removed)
+    }
+
+    /// Joins the chunks claimed in `assembling`, returning what the first one recorded
+    async fn assemble(
+        &self,
+        assembling: &Path,
+        total: usize,
+    ) -> MultipartResult<(StreamedFile, Option<FirstChunk>)> {
+        let (assembled, mut file) = StreamedFile::create(&self.root).await?;
+        for index in 0..total {
+            let mut part = File::open(part_path(assembling, index)).await?;
+            tokio::io::copy(&mut part, &mut file).await?;
+        }
+        file.flush().await?;
+
+        let first = match fs::read(assembling.join(FIRST_CHUNK)).await {
+            Ok(json) => serde_json::from_slice(&json).ok(),
+            Err(err) if err.kind() == ErrorKind::NotFound => None,
+            Err(err) => return Err(err.into()),
+        };
+
+        Ok((assembled, first))
+    }
+
+    /// Keeps the name and type of the first chunk, which describe the whole file
+    async fn record_first(&self, staging: &Path, info: &FileInfo) -> MultipartResult<()> {
+        let first = FirstChunk {
+            name: info.name.clone(),
+            content_type: info.content_type.clone(),
+        };
+        let json = serde_json::to_vec(&first)
+            .map_err(|err| MultipartError::StorageError(err.to_string()))?;
+        Ok(fs::write(staging.join(FIRST_CHUNK), json).await?)
+    }
+
+    fn required<T: std::str::FromStr>(
+        &self,
+        fields: &TextFields,
+        name: &str,
+    ) -> MultipartResult<T> {
+        let value = fields.parse(name)?;
+        Ok(value.ok_or_else(|| FormError::MissingField(name.to_string()))?)
+    }
+
+    /// Records the chunk count on the first chunk and makes later ones agree with it
+    async fn check_total(&self, staging: &Path, total: usize) -> MultipartResult<()> {
+        let path = staging.join("total");
+        match fs::read_to_string(&path).await {
+            Ok(recorded) if recorded.parse() == Ok(total) => Ok(()),
+            Ok(_) => Err(invalid(&self.total_field, "number of chunks changed")),
+            Err(err) if err.kind() == ErrorKind::NotFound => {
+                Ok(fs::write(&path, total.to_string()).await?)
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Which chunks are staged, by index, and their combined size
+    async fn tally(&self, staging: &Path, total: usize) -> MultipartResult<(Vec<bool>, usize)> {
+        let mut staged = vec![false; total];
+        let mut size = 0;
+
+        for (index, staged) in staged.iter_mut().enumerate() {
+            match fs::metadata(part_path(staging, index)).await {
+                Ok(meta) => {
+                    *staged = true;
+                    size += meta.len() as usize;
+                }
+                Err(err) if err.kind() == ErrorKind::NotFound => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        Ok((staged, size))
+    }
+}
+
+/// File in the staging directory describing the first chunk
+const FIRST_CHUNK: &str = "first.json";
+
+#[derive(Serialize, Deserialize)]
+struct FirstChunk {
+    name: String,
+    content_type: String,
+}
+
+async fn remove_staging(path: &Path) -> MultipartResult<()> {
+    match fs::remove_dir_all(path).await {
+        Err(err) if err.kind() != ErrorKind::NotFound => Err(err.into()),
+        _ => Ok(()),
+    }
+}
+
+fn part_path(staging: &Path, index: usize) -> PathBuf {
+    staging.join(format!("{}.part", index))
+}
+
+fn invalid(field: &str, message: &str) -> MultipartError {
+    FormError::InvalidValue {
+        field: field.to_string(),
+        message: message.to_string(),
+    }
+    .into()
+}
+
+/// Upload ids become directory names, so only a conservative set of characters is accepted
+fn is_upload_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= 128
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::uploader::tests::{generate_form_uploader, Part};
+
+    fn assembler(name: &str) -> ChunkAssembler {
+        let root = std::env::temp_dir().join(format!("medullah-chunks-{}", name));
+        let _ = std::fs::remove_dir_all(&root);
+        ChunkAssembler::new(root)
+    }
+
+    async fn send(
+        assembler: &ChunkAssembler,
+        index: &str,
+        data: &[u8],
+    ) -> MultipartResult<ChunkStatus> {
+        send_named(assembler, index, "movie.mp4", data).await
+    }
+
+    async fn send_named(
+        assembler: &ChunkAssembler,
+        index: &str,
+        name: &str,
+        data: &[u8],
+    ) -> MultipartResult<ChunkStatus> {
+        let mut uploader = generate_form_uploader(&[
+            Part::Text("uploadId", "abc-123"),
+            Part::Text("chunkIndex", index),
+            Part::Text("totalChunks", "3"),
+            Part::Text("filename", "film.mp4"),
+            Part::File("file", name, "video/mp4", data),
+        ])
+        .await;
+
+        let ud = UploadData {
+            field: "file",
+            ..UploadData::default()
+        };
+        assembler.accept(&mut uploader, ud).await
+    }
+
+    #[tokio::test]
+    async fn test_assembles_out_of_order_chunks() {
+        let assembler = assembler("assemble");
+
+        let status = send_named(&assembler, "2", "blob", b"ghi").await.unwrap();
+        assert!(matches!(
+            status,
+            ChunkStatus::Pending {
+                received: 1,
+                total: 3
+            }
+        ));
+        send(&assembler, "0", b"abc").await.unwrap();
+
+        let status = send_named(&assembler, "1", "blob", b"def").await.unwrap();
+        let ChunkStatus::Complete(file) = status else {
+            panic!("expected the file to be assembled");
+        };
+
+        assert_eq!(file.file().name, "movie.mp4");
+        assert_eq!(file.file().size, 9);
+        assert_eq!(std::fs::read(file.path()).unwrap(), b"abcdefghi");
+        assert!(!assembler.root.join("abc-123").exists());
+    }
+
+    #[tokio::test]
+    async fn test_rejects_invalid_chunks() {
+        let assembler = assembler("invalid").sequential(true).max_total_size(5);
+
+        let err = send(&assembler, "3", b"abc").await.unwrap_err();
+        assert_eq!(err.code(), "invalid_form");
+
+        let err = send(&assembler, "1", b"abc").await.unwrap_err();
+        assert_eq!(err.code(), "invalid_form");
+
+        send(&assembler, "0", b"abc").await.unwrap();
+        let err = send(&assembler, "1", b"def").await.unwrap_err();
+        assert_eq!(err.code(), "file_too_large");
+    }
+
+    #[tokio::test]
+    async fn test_filename_field_names_the_file() {
+        let assembler = assembler("filename").filename_field("filename");
+
+        for index in ["0", "1"] {
+            send_named(&assembler, index, "blob", b"abc").await.unwrap();
+        }
+        let status = send_named(&assembler, "2", "blob", b"def").await.unwrap();
+        let ChunkStatus::Complete(file) = status else {
+            panic!("expected the file to be assembled");
+        };
+
+        assert_eq!(file.file().name, "film.mp4");
+        assert_eq!(file.file().extension.as_deref(), Some("mp4"));
+        assert_eq!(file.file().content_type, "video/mp4");
+    }
+
+    #[tokio::test]
+    async fn test_failed_assembly_is_cleaned_up() {
+        let assembler = assembler("failed");
+
+        // a directory in place of the last chunk counts as staged but can't be read
+        std::fs::create_dir_all(assembler.root.join("abc-123/2.part")).unwrap();
+        send(&assembler, "0", b"abc").await.unwrap();
+        assert!(send(&assembler, "1", b"def").await.is_err());
+
+        assert!(!assembler.root.join("abc-123").exists());
+        assert!(!assembler.root.join("abc-123.assembling").exists());
+    }
+
+    #[tokio::test]
+    async fn test_abort_and_sweep() {
+        let assembler = assembler("sweep");
+
+        send(&assembler, "0", b"abc").await.unwrap();
+        std::fs::create_dir_all(assembler.root.join("abc-123.assembling")).unwrap();
+        assembler.abort("abc-123").await.unwrap();
+        assert!(!assembler.root.join("abc-123").exists());
+        assert!(!assembler.root.join("abc-123.assembling").exists());
+
+        send(&assembler, "0", b"abc").await.unwrap();
+        let hour = Duration::from_secs(3600);
+        assert_eq!(assembler.sweep(hour).await.unwrap(), 0);
+        assert!(assembler.root.join("abc-123").exists());
+
+        assert_eq!(assembler.sweep(Duration::ZERO).await.unwrap(), 1);
+        assert!(!assembler.root.join("abc-123").exists());
+    }
+}
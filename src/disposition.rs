@@ -0,0 +1,146 @@
+use std::collections::HashMap;
+
+/// Parses the parameters of a `Content-Disposition` header value (RFC 7578 / RFC 6266).
+///
+/// Keys are lowercased, quoted-string values are unescaped, and an RFC 5987 extended
+/// `filename*` value is percent-decoded into its charset and stored under `filename*`.
+pub(crate) fn parse(content_disposition: &str) -> HashMap<String, String> {
+    let mut variables = HashMap::new();
+
+    // the disposition type (`form-data`) is a token, so it cannot contain a `;`
+    let params = match content_disposition.split_once(';') {
+        Some((_, params)) => params,
+        None => return variables,
+    };
+
+    let mut chars = params.chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace() || *c == ';').is_some() {}
+        if chars.peek().is_none() {
+            break;
+        }
+
+        let mut key = String::new();
+        while let Some(c) = chars.next_if(|c| *c != '=' && *c != ';') {
+            key.push(c);
+        }
+
+        if chars.next_if_eq(&'=').is_none() {
+            continue;
+        }
+
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+
+        let mut value = String::new();
+        if chars.next_if_eq(&'"').is_some() {
+            while let Some(c) = chars.next() {
+                match c {
+                    '"' => break,
+                    '\\' => value.extend(chars.next()),
+                    c => value.push(c),
+                }
+            }
+            // ignore anything between the closing quote and the next parameter
+            while chars.next_if(|c| *c != ';').is_some() {}
+        } else {
+            while let Some(c) = chars.next_if(|c| *c != ';') {
+                value.push(c);
+            }
+            value = value.trim_end().to_string();
+        }
+
+        let key = key.trim().to_ascii_lowercase();
+        if key.ends_with('*') {
+            if let Some(decoded) = decode_ext_value(&value) {
+                variables.insert(key, decoded);
+            }
+        } else if !key.is_empty() {
+            variables.insert(key, value);
+        }
+    }
+
+    variables
+}
+
+/// Decodes an RFC 5987 `ext-value` such as `UTF-8''na%C3%AFve.txt`
+fn decode_ext_value(value: &str) -> Option<String> {
+    let mut parts = value.splitn(3, '\'');
+    let charset = parts.next()?.to_ascii_lowercase();
+    let _language = parts.next()?;
+    let bytes = percent_decode(parts.next()?);
+
+    match charset.as_str() {
+        "utf-8" => String::from_utf8(bytes).ok(),
+        "iso-8859-1" => Some(bytes.into_iter().map(char::from).collect()),
+        _ => None,
+    }
+}
+
+fn percent_decode(value: &str) -> Vec<u8> {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .filter(|h| h.iter().all(u8::is_ascii_hexdigit))
+            .and_then(|h| std::str::from_utf8(h).ok())
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+
+    decoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quoted_values_with_separators() {
+        let vars = parse(r#"form-data; name="doc"; filename="a;b=c.txt""#);
+        assert_eq!(vars["name"], "doc");
+        assert_eq!(vars["filename"], "a;b=c.txt");
+    }
+
+    #[test]
+    fn test_escaped_quotes() {
+        let vars = parse(r#"form-data; name="doc"; filename="say \"hi\".txt""#);
+        assert_eq!(vars["filename"], r#"say "hi".txt"#);
+    }
+
+    #[test]
+    fn test_extended_filename() {
+        let vars = parse("form-data; name=doc; filename*=UTF-8''na%C3%AFve%20file.txt");
+        assert_eq!(vars["name"], "doc");
+        assert_eq!(vars["filename*"], "naïve file.txt");
+
+        let vars = parse("form-data; name=doc; filename*=iso-8859-1'en'%E9t%E9.txt");
+        assert_eq!(vars["filename*"], "été.txt");
+    }
+
+    #[test]
+    fn test_percent_decode_requires_hex_digits() {
+        assert_eq!(percent_decode("a%+fb"), b"a%+fb");
+        assert_eq!(percent_decode("%2"), b"%2");
+        assert_eq!(percent_decode("%2f%2F"), b"//");
+    }
+
+    #[test]
+    fn test_case_insensitive_keys() {
+        let vars = parse(r#"form-data; NAME="doc"; FileName="a.txt""#);
+        assert_eq!(vars["name"], "doc");
+        assert_eq!(vars["filename"], "a.txt");
+    }
+}
@@ -0,0 +1,186 @@
+use crate::file::FileInfo;
+use crate::mime::essence;
+use crate::result::MultipartError::ValidationError;
+use crate::result::MultipartResult;
+use crate::result::MultipartValidationError::ExtensionError;
+
+/// Extensions per content type, the first one being the preferred extension;
+/// aliases of a type follow its canonical entry
+const EXTENSIONS: &[(&str, &[&str])] = &[
+    ("image/jpeg", &["jpg", "jpeg", "jpe", "jfif"]),
+    ("image/png", &["png"]),
+    ("image/gif", &["gif"]),
+    ("image/webp", &["webp"]),
+    ("image/avif", &["avif"]),
+    ("image/heic", &["heic", "heif"]),
+    ("image/bmp", &["bmp"]),
+    ("image/tiff", &["tif", "tiff"]),
+    ("image/x-icon", &["ico"]),
+    ("image/svg+xml", &["svg"]),
+    ("audio/mpeg", &["mp3"]),
+    ("audio/ogg", &["ogg", "oga", "opus"]),
+    ("audio/flac", &["flac"]),
+    ("audio/wav", &["wav"]),
+    ("audio/mp4", &["m4a"]),
+    ("video/mp4", &["mp4", "m4v"]),
+    ("video/webm", &["webm"]),
+    ("video/quicktime", &["mov"]),
+    ("video/x-msvideo", &["avi"]),
+    ("application/pdf", &["pdf"]),
+    ("application/zip", &["zip"]),
+    ("application/gzip", &["gz", "tgz", "tar.gz"]),
+    ("application/x-bzip2", &["bz2", "tar.bz2"]),
+    ("application/x-xz", &["xz", "tar.xz"]),
+    ("application/zstd", &["zst", "tar.zst"]),
+    ("application/x-tar", &["tar"]),
+    ("application/x-7z-compressed", &["7z"]),
+    ("application/vnd.rar", &["rar"]),
+    ("application/json", &["json"]),
+    ("application/xml", &["xml"]),
+    ("application/wasm", &["wasm"]),
+    ("application/x-msdownload", &["exe", "dll"]),
+    ("application/msword", &["doc"]),
+    ("application/vnd.ms-excel", &["xls"]),
+    ("application/vnd.ms-powerpoint", &["ppt"]),
+    (
+        "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+        &["docx"],
+    ),
+    (
+        "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+        &["xlsx"],
+    ),
+    (
+        "application/vnd.openxmlformats-officedocument.presentationml.presentation",
+        &["pptx"],
+    ),
+    ("application/vnd.oasis.opendocument.text", &["odt"]),
+    ("application/vnd.oasis.opendocument.spreadsheet", &["ods"]),
+    ("application/vnd.oasis.opendocument.presentation", &["odp"]),
+    ("application/epub+zip", &["epub"]),
+    ("application/java-archive", &["jar"]),
+    ("application/vnd.android.package-archive", &["apk"]),
+    ("text/plain", &["txt"]),
+    ("text/csv", &["csv"]),
+    ("text/html", &["html", "htm"]),
+    ("text/css", &["css"]),
+    ("text/markdown", &["md"]),
+    ("image/jpg", &["jpg"]),
+    ("image/pjpeg", &["jpg"]),
+    ("image/x-png", &["png"]),
+    ("image/vnd.microsoft.icon", &["ico"]),
+    ("audio/mp3", &["mp3"]),
+    ("audio/x-wav", &["wav"]),
+    ("application/x-gzip", &["gz"]),
+    ("application/x-zip-compressed", &["zip"]),
+    ("text/xml", &["xml"]),
+];
+
+/// Lowercased extension of a file name, keeping compound extensions such as `tar.gz`
+/// together; names without a dot and dotfiles like `.bashrc` have none
+pub fn file_extension(name: &str) -> Option<String> {
+    let name = name.rsplit(['/', '\\']).next().unwrap_or(name);
+    let (stem, ext) = name.trim_start_matches('.').rsplit_once('.')?;
+    if stem.is_empty() || ext.is_empty() || !ext.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return None;
+    }
+
+    let ext = ext.to_ascii_lowercase();
+    match stem.rsplit_once('.') {
+        Some((base, inner)) if !base.is_empty() && inner.eq_ignore_ascii_case("tar") => {
+            Some(format!("tar.{}", ext))
+        }
+        _ => Some(ext),
+    }
+}
+
+/// Preferred extension for a content type, e.g. `jpg` for `image/jpeg`
+pub fn mime_extension(content_type: &str) -> Option<&'static str> {
+    let content_type = essence(content_type);
+    EXTENSIONS
+        .iter()
+        .find(|(mime, _)| *mime == content_type)
+        .map(|(_, extensions)| extensions[0])
+}
+
+/// Content type files with the given extension are expected to have
+pub fn extension_mime(extension: &str) -> Option<&'static str> {
+    let extension = extension.trim_start_matches('.').to_ascii_lowercase();
+    EXTENSIONS
+        .iter()
+        .find(|(_, extensions)| extensions.contains(&extension.as_str()))
+        .map(|(mime, _)| *mime)
+}
+
+/// Checks the file's [inferred extension](FileInfo::inferred_extension) against
+/// `allowed`; an entry also accepts compound extensions ending in it, so `gz`
+/// accepts `tar.gz`
+pub(crate) fn validate_extension(file: &FileInfo, allowed: &[&str]) -> MultipartResult<()> {
+    if allowed.is_empty() {
+        return Ok(());
+    }
+
+    let Some(extension) = file.inferred_extension() else {
+        return Err(ValidationError(ExtensionError));
+    };
+
+    let matches = allowed.iter().any(|allowed| {
+        let allowed = allowed.trim_start_matches('.').to_ascii_lowercase();
+        *extension == allowed || extension.ends_with(&format!(".{}", allowed))
+    });
+
+    match matches {
+        true => Ok(()),
+        false => Err(ValidationError(ExtensionError)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_extension() {
+        assert_eq!(file_extension("photo.JPG"), Some("jpg".to_string()));
+        assert_eq!(file_extension("archive.tar.gz"), Some("tar.gz".to_string()));
+        assert_eq!(file_extension("v1.2.final.pdf"), Some("pdf".to_string()));
+        assert_eq!(file_extension(".config.json"), Some("json".to_string()));
+        assert_eq!(file_extension("dir.d/notes.txt"), Some("txt".to_string()));
+        assert_eq!(file_extension("README"), None);
+        assert_eq!(file_extension(".bashrc"), None);
+        assert_eq!(file_extension("trailing."), None);
+        assert_eq!(file_extension("report.final draft"), None);
+    }
+
+    #[test]
+    fn test_mime_mapping() {
+        assert_eq!(mime_extension("image/jpeg"), Some("jpg"));
+        assert_eq!(mime_extension("IMAGE/PJPEG"), Some("jpg"));
+        assert_eq!(mime_extension("text/plain; charset=utf-8"), Some("txt"));
+        assert_eq!(mime_extension("application/x-unknown"), None);
+
+        assert_eq!(extension_mime("JPEG"), Some("image/jpeg"));
+        assert_eq!(extension_mime(".tar.gz"), Some("application/gzip"));
+        assert_eq!(extension_mime("unknown"), None);
+    }
+
+    #[test]
+    fn test_validate_extension() {
+        let file = |name: &str| FileInfo::new("file".into(), name.into(), "".into());
+
+        assert!(validate_extension(&file("README"), &[]).is_ok());
+        assert!(validate_extension(&file("Report.PDF"), &["pdf"]).is_ok());
+        assert!(validate_extension(&file("backup.tar.gz"), &["gz"]).is_ok());
+        assert!(validate_extension(&file("backup.tar.gz"), &[".tar.gz"]).is_ok());
+        assert!(validate_extension(&file("shell.php"), &["pdf", "png"]).is_err());
+        assert!(validate_extension(&file("README"), &["pdf"]).is_err());
+
+        // once sniffed, the content decides over the name
+        let mut readme = file("README");
+        readme.detected_content_type = Some("image/png".into());
+        assert!(validate_extension(&readme, &["png"]).is_ok());
+        let mut disguised = file("photo.png");
+        disguised.detected_content_type = Some("application/pdf".into());
+        assert!(validate_extension(&disguised, &["png"]).is_err());
+    }
+}
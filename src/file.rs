@@ -2,7 +2,14 @@ use std::collections::HashMap;
 
 use ntex::http::HeaderMap;
 
+use crate::checksum::Checksums;
+use crate::disposition;
+use crate::extension::{extension_mime, file_extension, mime_extension};
+use crate::image::ImageInfo;
+use crate::mime::essence;
+use crate::pipeline::ImageVariant;
 use crate::result::{MultipartError, MultipartResult};
+use crate::sanitize::{random_storage_name, sanitize_filename};
 
 #[derive(Debug, Default, Clone)]
 pub struct FileInfo {
@@ -10,7 +17,16 @@ pub struct FileInfo {
     pub field: String,
     pub size: usize,
     pub content_type: String,
+    /// Content type detected from the leading bytes, when sniffing is enabled
+    pub detected_content_type: Option<String>,
+    /// Lowercased extension of `name`, see [`crate::file_extension`]
     pub extension: Option<String>,
+    /// Digests computed during capture, see [`crate::UploadData::checksums`]
+    pub checksums: Checksums,
+    /// Format and dimensions, when the field was validated as an image
+    pub image: Option<ImageInfo>,
+    /// Images derived by [`crate::CapturedFile::process`], such as thumbnails
+    pub variants: Vec<ImageVariant>,
     pub content_disposition_vars: HashMap<String, String>,
 }
 
@@ -20,38 +36,86 @@ impl FileInfo {
         let content_disposition = Self::get_content_disposition(headers)?;
 
         let variables = Self::parse_content_disposition(&content_disposition);
-        if !variables.contains_key("name") || !variables.contains_key("filename") {
-            return Err(MultipartError::InvalidContentDisposition);
-        }
 
-        let field = variables.get("name").cloned().unwrap();
-        let name = variables.get("filename").cloned().unwrap();
+        // RFC 6266: the extended `filename*` takes precedence over the plain `filename`
+        let filename = variables
+            .get("filename*")
+            .or_else(|| variables.get("filename"));
 
-        let binding = name.clone();
-        let split_name: Vec<&str> = binding.split('.').collect();
+        let (field, name) = match (variables.get("name"), filename) {
+            (Some(field), Some(name)) => (field.clone(), name.clone()),
+            _ => return Err(MultipartError::InvalidContentDisposition),
+        };
 
         Ok(Self {
+            content_disposition_vars: variables,
+            ..Self::new(field, name, content_type)
+        })
+    }
+
+    /// Describes a file received outside of a multipart body, e.g. through tus
+    pub(crate) fn new(field: String, name: String, content_type: String) -> Self {
+        Self {
+            extension: file_extension(&name),
             name,
             field,
             content_type,
+            detected_content_type: None,
             size: 0,
-            extension: split_name.last().map(|e| e.to_string()),
-            content_disposition_vars: variables,
-        })
+            checksums: Checksums::default(),
+            image: None,
+            variants: vec![],
+            content_disposition_vars: HashMap::new(),
+        }
     }
-    fn parse_content_disposition(content_disposition: &str) -> HashMap<String, String> {
-        let mut variables = HashMap::new();
-
-        for part in content_disposition.split(';') {
-            let part = part.trim();
-            if let Some((key, value)) = part.split_once('=') {
-                let key = key.trim().to_string();
-                let value = value.trim().trim_matches('"').to_string();
-                variables.insert(key, value);
+
+    /// Extension the file should carry: the name's extension when it agrees with the
+    /// detected content type, otherwise one inferred from the detected or declared type
+    pub fn inferred_extension(&self) -> Option<String> {
+        if let Some(detected) = &self.detected_content_type {
+            let named = self.extension.as_deref().and_then(extension_mime);
+            if named.is_some_and(|named| named == essence(detected)) {
+                return self.extension.clone();
+            }
+
+            if let Some(extension) = mime_extension(detected) {
+                return Some(extension.to_string());
             }
         }
 
-        variables
+        self.extension
+            .clone()
+            .or_else(|| mime_extension(&self.content_type).map(str::to_string))
+    }
+
+    /// Client filename made safe to use as a single path component
+    pub fn sanitized_name(&self) -> String {
+        sanitize_filename(&self.name)
+    }
+
+    /// Random UUID based name to store the file under, carrying its
+    /// [inferred extension](FileInfo::inferred_extension)
+    pub fn storage_name(&self) -> String {
+        random_storage_name(self.inferred_extension().as_deref())
+    }
+
+    /// Returns the field name when the part is a plain form field (has no `filename`)
+    pub(crate) fn text_field_name(headers: &HeaderMap) -> MultipartResult<Option<String>> {
+        let content_disposition = Self::get_content_disposition(headers)?;
+        let mut variables = Self::parse_content_disposition(&content_disposition);
+
+        if variables.contains_key("filename") || variables.contains_key("filename*") {
+            return Ok(None);
+        }
+
+        match variables.remove("name") {
+            None => Err(MultipartError::InvalidContentDisposition),
+            Some(name) => Ok(Some(name)),
+        }
+    }
+
+    fn parse_content_disposition(content_disposition: &str) -> HashMap<String, String> {
+        disposition::parse(content_disposition)
     }
 
     fn get_content_type(headers: &HeaderMap) -> MultipartResult<String> {
@@ -65,10 +129,10 @@ impl FileInfo {
     }
 
     fn get_content_disposition(headers: &HeaderMap) -> MultipartResult<String> {
+        // browsers send non-ASCII filenames as raw UTF-8, which `HeaderValue::to_str` rejects
         match headers.get("content-disposition") {
             None => Err(MultipartError::InvalidContentDisposition),
-            Some(header) => header
-                .to_str()
+            Some(header) => std::str::from_utf8(header.as_bytes())
                 .map(|v| v.to_string())
                 .map_err(|_| MultipartError::InvalidContentDisposition),
         }
@@ -77,7 +141,7 @@ impl FileInfo {
 
 #[cfg(test)]
 mod tests {
-    use ntex::http::header::{CONTENT_DISPOSITION, CONTENT_TYPE};
+    use ntex::http::header::{HeaderValue, CONTENT_DISPOSITION, CONTENT_TYPE};
 
     use super::*;
 
@@ -121,6 +185,56 @@ mod tests {
         ));
     }
 
+    #[tokio::test]
+    async fn test_create_prefers_extended_filename() {
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, "image/jpeg".parse().unwrap());
+        headers.insert(
+            CONTENT_DISPOSITION,
+            "form-data; name=\"image\"; filename=\"photo.jpg\"; filename*=UTF-8''%E5%86%99%E7%9C%9F.jpg"
+                .parse()
+                .unwrap(),
+        );
+
+        let file_info = FileInfo::create(&headers).unwrap();
+        assert_eq!(file_info.name, "写真.jpg");
+    }
+
+    #[tokio::test]
+    async fn test_create_from_raw_utf8_filename() {
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, "image/jpeg".parse().unwrap());
+        headers.insert(
+            CONTENT_DISPOSITION,
+            HeaderValue::from_bytes("form-data; name=\"image\"; filename=\"café.jpg\"".as_bytes())
+                .unwrap(),
+        );
+
+        let file_info = FileInfo::create(&headers).unwrap();
+        assert_eq!(file_info.name, "café.jpg");
+    }
+
+    #[tokio::test]
+    async fn test_text_field_name() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            CONTENT_DISPOSITION,
+            "form-data; name=\"title\"".parse().unwrap(),
+        );
+        assert_eq!(
+            FileInfo::text_field_name(&headers).unwrap(),
+            Some("title".to_string())
+        );
+
+        headers.insert(
+            CONTENT_DISPOSITION,
+            "form-data; name=\"image\"; filename=\"image.jpg\""
+                .parse()
+                .unwrap(),
+        );
+        assert_eq!(FileInfo::text_field_name(&headers).unwrap(), None);
+    }
+
     #[tokio::test]
     async fn test_parse_content_disposition() {
         let content_disposition = "form-data; name=\"image\"; filename=\"image.jpg\"";
@@ -129,4 +243,27 @@ mod tests {
         assert_eq!(variables.get("name"), Some(&"image".to_string()));
         assert_eq!(variables.get("filename"), Some(&"image.jpg".to_string()));
     }
+
+    #[test]
+    fn test_inferred_extension() {
+        let file = |name: &str, content_type: &str, detected: Option<&str>| FileInfo {
+            detected_content_type: detected.map(str::to_string),
+            ..FileInfo::new("file".into(), name.into(), content_type.into())
+        };
+
+        assert_eq!(file("README", "image/png", None).extension, None);
+        assert_eq!(
+            file("README", "image/png", None).inferred_extension(),
+            Some("png".to_string())
+        );
+        assert_eq!(
+            file("photo.JPEG", "image/jpeg", Some("image/jpeg")).inferred_extension(),
+            Some("jpeg".to_string())
+        );
+        assert_eq!(
+            file("photo.jpg", "image/jpeg", Some("image/png")).inferred_extension(),
+            Some("png".to_string())
+        );
+        assert_eq!(file(".bashrc", "", None).inferred_extension(), None);
+    }
 }
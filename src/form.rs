@@ -0,0 +1,666 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::Formatter;
+use std::ops::{Deref, DerefMut};
+use std::rc::Rc;
+
+use ntex::http::Payload;
+use ntex::web::{FromRequest, HttpRequest};
+use serde::de::value::{SeqDeserializer, StringDeserializer};
+use serde::de::{
+    DeserializeOwned, DeserializeSeed, Error as DeError, IntoDeserializer, MapAccess, Unexpected,
+    Visitor,
+};
+use serde::{forward_to_deserialize_any, Deserialize, Deserializer};
+
+use crate::captured::CapturedFile;
+use crate::result::{FormError, MultipartError, MultipartResult};
+use crate::text::{TextFields, DEFAULT_TEXT_FIELD_LIMIT};
+use crate::uploader::{RequestLimits, UploadData, Uploader};
+
+/// Default size limit of file fields the [`MultipartForm`] extractor buffers in memory
+/// without their own `upper_size` (10 MiB); raise it with
+/// [`MultipartFormConfig::default_upper_size`]
+pub const DEFAULT_FORM_FILE_LIMIT: usize = 10 * 1024 * 1024;
+
+const CAPTURED_FILE_TOKEN: &str = "$medullah_multipart::CapturedFile";
+
+/// Captured files of one form, taken out by index as `CapturedFile`s deserialize
+type FileStore = Rc<RefCell<Vec<Option<CapturedFile>>>>;
+
+thread_local! {
+    // stores of the forms being deserialized on this thread, innermost last; a file
+    // travels through serde as a newtype holding its index, so it survives buffering
+    // by `#[serde(flatten)]` and is found whatever order fields are visited in
+    static FILE_STORES: RefCell<Vec<FileStore>> = const { RefCell::new(vec![]) };
+}
+
+/// Makes a form's files available to `CapturedFile::deserialize` while it lives
+struct StoreScope;
+
+impl StoreScope {
+    fn enter(store: FileStore) -> Self {
+        FILE_STORES.with(|stores| stores.borrow_mut().push(store));
+        StoreScope
+    }
+}
+
+impl Drop for StoreScope {
+    fn drop(&mut self) {
+        FILE_STORES.with(|stores| stores.borrow_mut().pop());
+    }
+}
+
+/// Takes the file at `index` out of the innermost form being deserialized
+fn take_file(index: usize) -> Option<CapturedFile> {
+    FILE_STORES.with(|stores| {
+        let stores = stores.borrow();
+        let mut files = stores.last()?.borrow_mut();
+        files.get_mut(index)?.take()
+    })
+}
+
+/// Extracts a multipart request into `T`, where file fields are declared as
+/// [`CapturedFile`] (or `Option`/`Vec` of it) and text fields as any serde-deserializable type
+pub struct MultipartForm<T>(pub T);
+
+impl<T> MultipartForm<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> Deref for MultipartForm<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> DerefMut for MultipartForm<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
+
+/// Limits applied by the [`MultipartForm`] extractor, registered as app state
+pub struct MultipartFormConfig {
+    files: Vec<UploadData<'static>>,
+    default_upper_size: Option<usize>,
+    text_limits: HashMap<String, usize>,
+    default_text_limit: usize,
+    limits: RequestLimits,
+}
+
+impl Default for MultipartFormConfig {
+    fn default() -> Self {
+        Self {
+            files: vec![],
+            default_upper_size: Some(DEFAULT_FORM_FILE_LIMIT),
+            text_limits: HashMap::new(),
+            default_text_limit: DEFAULT_TEXT_FIELD_LIMIT,
+            limits: RequestLimits::default(),
+        }
+    }
+}
+
+impl MultipartFormConfig {
+    /// Validates the file field named in `ud` against it
+    pub fn file(mut self, ud: UploadData<'static>) -> Self {
+        self.files.push(ud);
+        self
+    }
+
+    /// Maximum size of file fields that have no explicit [`UploadData`] or no `upper_size`
+    /// in it, defaults to [`DEFAULT_FORM_FILE_LIMIT`]
+    pub fn default_upper_size(mut self, upper_size: usize) -> Self {
+        self.default_upper_size = Some(upper_size);
+        self
+    }
+
+    pub fn text_field_limit(mut self, field: &str, upper_size: usize) -> Self {
+        self.text_limits.insert(field.to_string(), upper_size);
+        self
+    }
+
+    pub fn default_text_field_limit(mut self, upper_size: usize) -> Self {
+        self.default_text_limit = upper_size;
+        self
+    }
+
+    /// Limits enforced across all parts of the request
+    pub fn limits(mut self, limits: RequestLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+}
+
+impl<T, Err> FromRequest<Err> for MultipartForm<T>
+where
+    T: DeserializeOwned,
+{
+    type Error = MultipartError;
+
+    async fn from_request(req: &HttpRequest, payload: &mut Payload) -> MultipartResult<Self> {
+        let mut uploader = match <Uploader as FromRequest<Err>>::from_request(req, payload).await {
+            Ok(uploader) => uploader,
+            Err(infallible) => match infallible {},
+        };
+
+        let default_config = MultipartFormConfig::default();
+        let config = req
+            .app_state::<MultipartFormConfig>()
+            .unwrap_or(&default_config);
+
+        uploader.limits(config.limits);
+        uploader.default_text_field_limit(config.default_text_limit);
+        for (field, limit) in &config.text_limits {
+            uploader.text_field_limit(field, *limit);
+        }
+
+        let files = uploader
+            .capture_every(&config.files, config.default_upper_size)
+            .await?;
+
+        let form = deserialize_form(uploader.text_fields().clone(), files)?;
+        Ok(MultipartForm(form))
+    }
+}
+
+pub(crate) fn deserialize_form<T: DeserializeOwned>(
+    text: TextFields,
+    files: HashMap<String, Vec<CapturedFile>>,
+) -> Result<T, FormError> {
+    let mut values: Vec<(String, FormValue)> = text
+        .into_values()
+        .into_iter()
+        .map(|(field, values)| (field, FormValue::Text(values)))
+        .collect();
+
+    let store = FileStore::default();
+    for (field, files) in files {
+        let mut stored = store.borrow_mut();
+        let indices = (stored.len()..stored.len() + files.len()).collect();
+        stored.extend(files.into_iter().map(Some));
+        values.push((field, FormValue::Files(indices)));
+    }
+
+    let _scope = StoreScope::enter(store);
+    T::deserialize(FormDeserializer { values })
+}
+
+impl<'de> Deserialize<'de> for CapturedFile {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_newtype_struct(CAPTURED_FILE_TOKEN, CapturedFileVisitor)
+    }
+}
+
+struct CapturedFileVisitor;
+
+impl<'de> Visitor<'de> for CapturedFileVisitor {
+    type Value = CapturedFile;
+
+    fn expecting(&self, f: &mut Formatter) -> std::fmt::Result {
+        f.write_str("a file field of a multipart form")
+    }
+
+    fn visit_newtype_struct<D: Deserializer<'de>>(
+        self,
+        index: D,
+    ) -> Result<CapturedFile, D::Error> {
+        let index = usize::deserialize(index)?;
+        take_file(index).ok_or_else(|| D::Error::custom("file was already taken"))
+    }
+}
+
+enum FormValue {
+    Text(Vec<String>),
+    /// Indices into the form's [`FileStore`]
+    Files(Vec<usize>),
+}
+
+struct FormDeserializer {
+    values: Vec<(String, FormValue)>,
+}
+
+impl<'de> Deserializer<'de> for FormDeserializer {
+    type Error = FormError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, FormError> {
+        visitor.visit_map(FormMapAccess {
+            values: self.values.into_iter(),
+            current: None,
+        })
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct seq tuple
+        tuple_struct map struct enum identifier ignored_any
+    }
+}
+
+struct FormMapAccess {
+    values: std::vec::IntoIter<(String, FormValue)>,
+    current: Option<(String, FormValue)>,
+}
+
+impl<'de> MapAccess<'de> for FormMapAccess {
+    type Error = FormError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, FormError> {
+        match self.values.next() {
+            None => Ok(None),
+            Some((field, value)) => {
+                let key: StringDeserializer<FormError> = field.clone().into_deserializer();
+                self.current = Some((field, value));
+                seed.deserialize(key).map(Some)
+            }
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, FormError> {
+        let (field, value) = self
+            .current
+            .take()
+            .ok_or_else(|| FormError::Custom("value requested before key".to_string()))?;
+
+        let result = match value {
+            FormValue::Text(values) => seed.deserialize(TextDeserializer(values)),
+            FormValue::Files(files) => seed.deserialize(FileDeserializer(files)),
+        };
+
+        result.map_err(|err| err.with_field(&field))
+    }
+}
+
+struct TextDeserializer(Vec<String>);
+
+impl TextDeserializer {
+    fn first(self) -> String {
+        self.0.into_iter().next().unwrap_or_default()
+    }
+}
+
+impl<'de> IntoDeserializer<'de, FormError> for TextDeserializer {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+macro_rules! deserialize_parsed {
+    ($($method:ident => $visit:ident,)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, FormError> {
+                let value = self.first();
+                match value.parse() {
+                    Ok(parsed) => visitor.$visit(parsed),
+                    Err(_) => Err(FormError::invalid_value(Unexpected::Str(&value), &visitor)),
+                }
+            }
+        )*
+    };
+}
+
+impl<'de> Deserializer<'de> for TextDeserializer {
+    type Error = FormError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, FormError> {
+        match self.0.len() {
+            1 => visitor.visit_string(self.first()),
+            _ => self.deserialize_seq(visitor),
+        }
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, FormError> {
+        let value = self.first();
+        match value.as_str() {
+            "true" | "on" | "1" => visitor.visit_bool(true),
+            "false" | "off" | "0" => visitor.visit_bool(false),
+            _ => Err(FormError::invalid_value(Unexpected::Str(&value), &visitor)),
+        }
+    }
+
+    deserialize_parsed! {
+        deserialize_i8 => visit_i8,
+        deserialize_i16 => visit_i16,
+        deserialize_i32 => visit_i32,
+        deserialize_i64 => visit_i64,
+        deserialize_i128 => visit_i128,
+        deserialize_u8 => visit_u8,
+        deserialize_u16 => visit_u16,
+        deserialize_u32 => visit_u32,
+        deserialize_u64 => visit_u64,
+        deserialize_u128 => visit_u128,
+        deserialize_f32 => visit_f32,
+        deserialize_f64 => visit_f64,
+        deserialize_char => visit_char,
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, FormError> {
+        visitor.visit_string(self.first())
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, FormError> {
+        visitor.visit_string(self.first())
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, FormError> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, FormError> {
+        if name == CAPTURED_FILE_TOKEN {
+            return Err(FormError::invalid_type(
+                Unexpected::Str(&self.first()),
+                &visitor,
+            ));
+        }
+
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, FormError> {
+        let values = self
+            .0
+            .into_iter()
+            .map(|value| TextDeserializer(vec![value]));
+        let mut seq = SeqDeserializer::new(values);
+        let value = visitor.visit_seq(&mut seq)?;
+        seq.end()?;
+        Ok(value)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, FormError> {
+        let value: StringDeserializer<FormError> = self.first().into_deserializer();
+        value.deserialize_enum(name, variants, visitor)
+    }
+
+    forward_to_deserialize_any! {
+        bytes byte_buf unit unit_struct tuple tuple_struct map struct identifier ignored_any
+    }
+}
+
+struct FileDeserializer(Vec<usize>);
+
+impl<'de> IntoDeserializer<'de, FormError> for FileDeserializer {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+impl<'de> Deserializer<'de> for FileDeserializer {
+    type Error = FormError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, FormError> {
+        match self.0.as_slice() {
+            [index] => visitor.visit_newtype_struct(index.into_deserializer()),
+            _ => self.deserialize_seq(visitor),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, FormError> {
+        match (name, self.0.as_slice()) {
+            (CAPTURED_FILE_TOKEN, [_]) => self.deserialize_any(visitor),
+            (CAPTURED_FILE_TOKEN, files) => Err(FormError::Custom(format!(
+                "expected a single file, got {}",
+                files.len()
+            ))),
+            _ => Err(FormError::invalid_type(Unexpected::Other("file"), &visitor)),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, FormError> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, FormError> {
+        let files = self
+            .0
+            .into_iter()
+            .map(|index| FileDeserializer(vec![index]));
+        let mut seq = SeqDeserializer::new(files);
+        let value = visitor.visit_seq(&mut seq)?;
+        seq.end()?;
+        Ok(value)
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, FormError> {
+        visitor.visit_unit()
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct tuple tuple_struct map struct enum identifier
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ntex::http::header::CONTENT_TYPE;
+    use ntex::web::test::TestRequest;
+    use ntex::web::DefaultError;
+    use serde::Deserialize;
+
+    use super::*;
+    use crate::file::FileInfo;
+
+    #[derive(Debug, Deserialize)]
+    struct Profile {
+        name: String,
+        age: u8,
+        subscribed: bool,
+        tags: Vec<String>,
+        avatar: CapturedFile,
+        cover: Option<CapturedFile>,
+    }
+
+    #[derive(Debug, Deserialize)]
+    #[serde(deny_unknown_fields)]
+    #[allow(dead_code)]
+    struct Strict {
+        name: String,
+    }
+
+    fn captured(field: &str, name: &str) -> CapturedFile {
+        let info = FileInfo {
+            name: name.to_string(),
+            field: field.to_string(),
+            ..FileInfo::default()
+        };
+
+        CapturedFile::new(info, vec![])
+    }
+
+    fn text(values: &[(&str, &str)]) -> TextFields {
+        let mut fields = TextFields::default();
+        for (field, value) in values {
+            fields.insert(field.to_string(), value.to_string());
+        }
+        fields
+    }
+
+    #[test]
+    fn test_deserialize_text_and_files() {
+        let text = text(&[
+            ("name", "Ada"),
+            ("age", "36"),
+            ("subscribed", "on"),
+            ("tags", "a"),
+            ("tags", "b"),
+        ]);
+        let files = HashMap::from([("avatar".to_string(), vec![captured("avatar", "a.png")])]);
+
+        let profile: Profile = deserialize_form(text, files).unwrap();
+        assert_eq!(profile.name, "Ada");
+        assert_eq!(profile.age, 36);
+        assert!(profile.subscribed);
+        assert_eq!(profile.tags, ["a", "b"]);
+        assert_eq!(profile.avatar.file().name, "a.png");
+        assert!(profile.cover.is_none());
+    }
+
+    #[test]
+    fn test_missing_field() {
+        let text = text(&[("name", "Ada"), ("age", "36"), ("subscribed", "off")]);
+        let err = deserialize_form::<Profile>(text, HashMap::new()).unwrap_err();
+        assert_eq!(err, FormError::MissingField("tags".to_string()));
+    }
+
+    #[test]
+    fn test_unknown_field() {
+        let text = text(&[("name", "Ada"), ("extra", "1")]);
+        let err = deserialize_form::<Strict>(text, HashMap::new()).unwrap_err();
+        assert_eq!(err, FormError::UnknownField("extra".to_string()));
+    }
+
+    #[test]
+    fn test_invalid_value() {
+        let text = text(&[("name", "Ada"), ("age", "old")]);
+        let err = deserialize_form::<Profile>(text, HashMap::new()).unwrap_err();
+        assert!(matches!(err, FormError::InvalidValue { field, .. } if field == "age"));
+    }
+
+    #[test]
+    fn test_text_for_file_field() {
+        let text = text(&[("avatar", "not-a-file")]);
+        #[derive(Debug, Deserialize)]
+        #[allow(dead_code)]
+        struct Avatar {
+            avatar: CapturedFile,
+        }
+
+        let err = deserialize_form::<Avatar>(text, HashMap::new()).unwrap_err();
+        assert!(matches!(err, FormError::InvalidValue { field, .. } if field == "avatar"));
+    }
+
+    #[test]
+    fn test_files_survive_flatten_and_nesting() {
+        #[derive(Debug, Deserialize)]
+        struct Images {
+            photos: Vec<CapturedFile>,
+            avatar: CapturedFile,
+        }
+
+        #[derive(Debug, Deserialize)]
+        struct Album {
+            title: String,
+            #[serde(flatten)]
+            images: Images,
+        }
+
+        let files = HashMap::from([
+            ("avatar".to_string(), vec![captured("avatar", "a.png")]),
+            (
+                "photos".to_string(),
+                vec![captured("photos", "1.png"), captured("photos", "2.png")],
+            ),
+        ]);
+
+        let album: Album = deserialize_form(text(&[("title", "Trip")]), files).unwrap();
+        assert_eq!(album.title, "Trip");
+        assert_eq!(album.images.avatar.file().name, "a.png");
+        let names: Vec<_> = album.images.photos.iter().map(|p| &p.file().name).collect();
+        assert_eq!(names, ["1.png", "2.png"]);
+    }
+
+    #[test]
+    fn test_rejects_several_files_for_single_field() {
+        #[derive(Debug, Deserialize)]
+        #[allow(dead_code)]
+        struct Avatar {
+            avatar: Option<CapturedFile>,
+        }
+
+        let files = HashMap::from([(
+            "avatar".to_string(),
+            vec![captured("avatar", "a.png"), captured("avatar", "b.png")],
+        )]);
+
+        let err = deserialize_form::<Avatar>(TextFields::default(), files).unwrap_err();
+        assert!(matches!(err, FormError::InvalidValue { field, .. } if field == "avatar"));
+    }
+
+    #[tokio::test]
+    async fn test_from_request() {
+        let body = "--boundary\r\n\
+            Content-Disposition: form-data; name=\"name\"\r\n\r\n\
+            Ada\r\n\
+            --boundary\r\n\
+            Content-Disposition: form-data; name=\"avatar\"; filename=\"a.png\"\r\n\
+            Content-Type: image/png\r\n\r\n\
+            png\r\n\
+            --boundary--\r\n";
+
+        #[derive(Deserialize)]
+        struct Upload {
+            name: String,
+            avatar: CapturedFile,
+        }
+
+        let (req, mut payload) = TestRequest::default()
+            .header(CONTENT_TYPE, "multipart/form-data; boundary=boundary")
+            .set_payload(body)
+            .to_http_parts();
+
+        let form =
+            <MultipartForm<Upload> as FromRequest<DefaultError>>::from_request(&req, &mut payload)
+                .await
+                .unwrap();
+
+        assert_eq!(form.name, "Ada");
+        assert_eq!(form.avatar.file().size, 3);
+    }
+
+    #[tokio::test]
+    async fn test_from_request_bounds_files_by_default() {
+        let mut body = b"--boundary\r\n\
+            Content-Disposition: form-data; name=\"avatar\"; filename=\"a.png\"\r\n\
+            Content-Type: image/png\r\n\r\n"
+            .to_vec();
+        body.extend(vec![0; DEFAULT_FORM_FILE_LIMIT + 1]);
+        body.extend_from_slice(b"\r\n--boundary--\r\n");
+
+        #[derive(Deserialize)]
+        #[allow(dead_code)]
+        struct Upload {
+            avatar: CapturedFile,
+        }
+
+        let (req, mut payload) = TestRequest::default()
+            .header(CONTENT_TYPE, "multipart/form-data; boundary=boundary")
+            .set_payload(body)
+            .to_http_parts();
+
+        let result =
+            <MultipartForm<Upload> as FromRequest<DefaultError>>::from_request(&req, &mut payload)
+                .await;
+        assert!(matches!(
+            result,
+            Err(MultipartError::ValidationError(
+                crate::result::MultipartValidationError::UpperSizeError
+            ))
+        ));
+    }
+}
@@ -0,0 +1,507 @@
+use crate::result::MultipartError::ValidationError;
+use crate::result::MultipartResult;
+use crate::result::MultipartValidationError::{
+    ImageAspectRatioError, ImageDimensionError, ImageFormatError, InvalidImage,
+};
+
+/// How much of the upload is buffered while looking for the image header; JPEGs
+/// may carry large metadata segments before the frame header
+pub(crate) const IMAGE_HEADER_LIMIT: usize = 256 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    Png,
+    Jpeg,
+    Gif,
+    WebP,
+    Bmp,
+}
+
+impl ImageFormat {
+    pub fn mime(&self) -> &'static str {
+        match self {
+            ImageFormat::Png => "image/png",
+            ImageFormat::Jpeg => "image/jpeg",
+            ImageFormat::Gif => "image/gif",
+            ImageFormat::WebP => "image/webp",
+            ImageFormat::Bmp => "image/bmp",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ImageFormat::Png => "png",
+            ImageFormat::Jpeg => "jpg",
+            ImageFormat::Gif => "gif",
+            ImageFormat::WebP => "webp",
+            ImageFormat::Bmp => "bmp",
+        }
+    }
+}
+
+/// Format and dimensions read from an image header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImageInfo {
+    pub format: ImageFormat,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Requirements an uploaded image must meet, see [`crate::UploadData::image`]
+#[derive(Debug, Default, Clone)]
+pub struct ImageConstraints {
+    /// Accepted formats; empty accepts any format that can be decoded
+    pub formats: Vec<ImageFormat>,
+    pub min_width: Option<u32>,
+    pub max_width: Option<u32>,
+    pub min_height: Option<u32>,
+    pub max_height: Option<u32>,
+    /// Smallest accepted width / height ratio
+    pub min_aspect_ratio: Option<f64>,
+    /// Largest accepted width / height ratio
+    pub max_aspect_ratio: Option<f64>,
+}
+
+impl ImageConstraints {
+    pub(crate) fn check(&self, image: &ImageInfo) -> MultipartResult<()> {
+        if !self.formats.is_empty() && !self.formats.contains(&image.format) {
+            return Err(ValidationError(ImageFormatError(image.format)));
+        }
+
+        let (width, height) = (image.width, image.height);
+        let too_small = |value, min: Option<u32>| min.is_some_and(|min| value < min);
+        let too_large = |value, max: Option<u32>| max.is_some_and(|max| value > max);
+
+        if too_small(width, self.min_width)
+            || too_large(width, self.max_width)
+            || too_small(height, self.min_height)
+            || too_large(height, self.max_height)
+        {
+            return Err(ValidationError(ImageDimensionError { width, height }));
+        }
+
+        let ratio = width as f64 / height as f64;
+        if self.min_aspect_ratio.is_some_and(|min| ratio < min)
+            || self.max_aspect_ratio.is_some_and(|max| ratio > max)
+        {
+            return Err(ValidationError(ImageAspectRatioError { width, height }));
+        }
+
+        Ok(())
+    }
+}
+
+/// Reads the format and dimensions from the start of an image, `None` when the
+/// header is incomplete or the format is not recognised
+pub fn image_info(bytes: &[u8]) -> Option<ImageInfo> {
+    let (format, width, height) = if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+        if bytes.get(12..16)? != b"IHDR" {
+            return None;
+        }
+        (ImageFormat::Png, be32(bytes, 16)?, be32(bytes, 20)?)
+    } else if bytes.starts_with(b"GIF87a") || bytes.starts_with(b"GIF89a") {
+        (ImageFormat::Gif, le16(bytes, 6)?, le16(bytes, 8)?)
+    } else if bytes.starts_with(b"\xff\xd8") {
+        match walk_jpeg(bytes, 2) {
+            JpegWalk::Frame(width, height) => (ImageFormat::Jpeg, width, height),
+            _ => return None,
+        }
+    } else if bytes.starts_with(b"RIFF") && bytes.get(8..12)? == b"WEBP" {
+        let (width, height) = webp_dimensions(bytes)?;
+        (ImageFormat::WebP, width, height)
+    } else if bytes.starts_with(b"BM") {
+        let (width, height) = bmp_dimensions(bytes)?;
+        (ImageFormat::Bmp, width, height)
+    } else {
+        return None;
+    };
+
+    image(format, width, height)
+}
+
+fn image(format: ImageFormat, width: u32, height: u32) -> Option<ImageInfo> {
+    if width == 0 || height == 0 {
+        return None;
+    }
+
+    Some(ImageInfo {
+        format,
+        width,
+        height,
+    })
+}
+
+/// How far walking the JPEG segments got
+enum JpegWalk {
+    /// The frame header was found
+    Frame(u32, u32),
+    /// The walk can continue at `resume` once `needed` bytes are available
+    Incomplete {
+        resume: usize,
+        needed: usize,
+    },
+    Invalid,
+}
+
+fn walk_jpeg(bytes: &[u8], mut pos: usize) -> JpegWalk {
+    loop {
+        match bytes.get(pos) {
+            Some(0xff) => {}
+            Some(_) => return JpegWalk::Invalid,
+            None => {
+                return JpegWalk::Incomplete {
+                    resume: pos,
+                    needed: pos + 2,
+                }
+            }
+        }
+
+        // markers may be preceded by any number of 0xff fill bytes
+        while bytes.get(pos + 1) == Some(&0xff) {
+            pos += 1;
+        }
+
+        let Some(&marker) = bytes.get(pos + 1) else {
+            return JpegWalk::Incomplete {
+                resume: pos,
+                needed: pos + 2,
+            };
+        };
+
+        match marker {
+            0xc0..=0xcf if !matches!(marker, 0xc4 | 0xc8 | 0xcc) => {
+                return match (be16(bytes, pos + 7), be16(bytes, pos + 5)) {
+                    (Some(width), Some(height)) => JpegWalk::Frame(width, height),
+                    _ => JpegWalk::Incomplete {
+                        resume: pos,
+                        needed: pos + 9,
+                    },
+                };
+            }
+            0x01 | 0xd0..=0xd8 => pos += 2,
+            0xd9 | 0xda => return JpegWalk::Invalid,
+            _ => match be16(bytes, pos + 2) {
+                Some(len) => pos += 2 + len as usize,
+                None => {
+                    return JpegWalk::Incomplete {
+                        resume: pos,
+                        needed: pos + 4,
+                    }
+                }
+            },
+        }
+    }
+}
+
+/// EXIF orientation of a JPEG, from 1 to 8, telling how its stored pixels must
+/// be turned for display; 1 when it carries none
+pub(crate) fn exif_orientation(bytes: &[u8]) -> u16 {
+    jpeg_exif(bytes).and_then(tiff_orientation).unwrap_or(1)
+}
+
+/// The TIFF structure of the first EXIF segment before the image data
+fn jpeg_exif(bytes: &[u8]) -> Option<&[u8]> {
+    if !bytes.starts_with(b"\xff\xd8") {
+        return None;
+    }
+
+    let mut pos = 2;
+    loop {
+        if *bytes.get(pos)? != 0xff {
+            return None;
+        }
+
+        match *bytes.get(pos + 1)? {
+            0xff => pos += 1,
+            0x01 | 0xd0..=0xd8 => pos += 2,
+            0xd9 | 0xda => return None,
+            marker => {
+                let len = be16(bytes, pos + 2)? as usize;
+                let body = bytes.get(pos + 4..pos + 2 + len)?;
+                if let (0xe1, Some(tiff)) = (marker, body.strip_prefix(b"Exif\0\0")) {
+                    return Some(tiff);
+                }
+                pos += 2 + len;
+            }
+        }
+    }
+}
+
+pub(crate) fn tiff_orientation(tiff: &[u8]) -> Option<u16> {
+    let little_endian = match tiff.get(..2)? {
+        b"II" => true,
+        b"MM" => false,
+        _ => return None,
+    };
+    let u16_at = |at| match little_endian {
+        true => le16(tiff, at),
+        false => be16(tiff, at),
+    };
+    let u32_at = |at| match little_endian {
+        true => le32(tiff, at),
+        false => be32(tiff, at),
+    };
+
+    let directory = u32_at(4)? as usize;
+    let entries = u16_at(directory)? as usize;
+    let entry = (0..entries)
+        .map(|i| directory + 2 + i * 12)
+        .find(|&entry| u16_at(entry) == Some(0x0112))?;
+
+    u16_at(entry + 8)
+        .map(|orientation| orientation as u16)
+        .filter(|orientation| (1..=8).contains(orientation))
+}
+
+fn webp_dimensions(bytes: &[u8]) -> Option<(u32, u32)> {
+    match bytes.get(12..16)? {
+        b"VP8 " => {
+            if bytes.get(23..26)? != b"\x9d\x01\x2a" {
+                return None;
+            }
+            Some((le16(bytes, 26)? & 0x3fff, le16(bytes, 28)? & 0x3fff))
+        }
+        b"VP8L" => {
+            if *bytes.get(20)? != 0x2f {
+                return None;
+            }
+            let bits = le32(bytes, 21)?;
+            Some(((bits & 0x3fff) + 1, ((bits >> 14) & 0x3fff) + 1))
+        }
+        b"VP8X" => Some((le24(bytes, 24)? + 1, le24(bytes, 27)? + 1)),
+        _ => None,
+    }
+}
+
+fn bmp_dimensions(bytes: &[u8]) -> Option<(u32, u32)> {
+    // OS/2 headers store 16 bit dimensions, later ones signed 32 bit with a
+    // negative height for top-down bitmaps
+    match le32(bytes, 14)? {
+        12 => Some((le16(bytes, 18)?, le16(bytes, 20)?)),
+        _ => {
+            let width = le32(bytes, 18)? as i32;
+            let height = le32(bytes, 22)? as i32;
+            Some((width.unsigned_abs(), height.unsigned_abs()))
+        }
+    }
+}
+
+fn be16(bytes: &[u8], at: usize) -> Option<u32> {
+    let b = bytes.get(at..at + 2)?;
+    Some(u16::from_be_bytes([b[0], b[1]]) as u32)
+}
+
+fn be32(bytes: &[u8], at: usize) -> Option<u32> {
+    let b = bytes.get(at..at + 4)?;
+    Some(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+fn le16(bytes: &[u8], at: usize) -> Option<u32> {
+    let b = bytes.get(at..at + 2)?;
+    Some(u16::from_le_bytes([b[0], b[1]]) as u32)
+}
+
+fn le24(bytes: &[u8], at: usize) -> Option<u32> {
+    let b = bytes.get(at..at + 3)?;
+    Some(u32::from_le_bytes([b[0], b[1], b[2], 0]))
+}
+
+fn le32(bytes: &[u8], at: usize) -> Option<u32> {
+    let b = bytes.get(at..at + 4)?;
+    Some(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+/// Buffers the start of a field until its image header can be decoded
+#[derive(Default)]
+pub(crate) struct ImageProbe {
+    head: Vec<u8>,
+    image: Option<ImageInfo>,
+    /// Where the JPEG segment walk continues, so segments are only parsed once
+    resume: usize,
+    /// Length `head` must reach before parsing can make progress again
+    needed: usize,
+}
+
+impl ImageProbe {
+    /// Feeds the next chunk, returning the image once its header has been decoded
+    pub(crate) fn feed(&mut self, chunk: &[u8]) -> MultipartResult<Option<ImageInfo>> {
+        if self.image.is_some() {
+            return Ok(None);
+        }
+
+        let wanted = IMAGE_HEADER_LIMIT - self.head.len();
+        self.head
+            .extend_from_slice(&chunk[..wanted.min(chunk.len())]);
+
+        if self.head.len() >= self.needed {
+            self.image = self.parse();
+        }
+
+        if self.image.is_none() && self.head.len() >= IMAGE_HEADER_LIMIT {
+            return Err(ValidationError(InvalidImage));
+        }
+
+        Ok(self.image)
+    }
+
+    fn parse(&mut self) -> Option<ImageInfo> {
+        // the other formats keep their dimensions at fixed offsets near the start
+        if !self.head.starts_with(b"\xff\xd8") {
+            return image_info(&self.head);
+        }
+
+        match walk_jpeg(&self.head, self.resume.max(2)) {
+            JpegWalk::Frame(width, height) => image(ImageFormat::Jpeg, width, height),
+            JpegWalk::Incomplete { resume, needed } => {
+                self.resume = resume;
+                self.needed = needed;
+                None
+            }
+            JpegWalk::Invalid => {
+                self.needed = usize::MAX;
+                None
+            }
+        }
+    }
+
+    /// The decoded image, failing when the whole field went by without a valid header
+    pub(crate) fn finish(&self) -> MultipartResult<ImageInfo> {
+        self.image.ok_or(ValidationError(InvalidImage))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let mut bytes = b"\x89PNG\r\n\x1a\n\x00\x00\x00\x0dIHDR".to_vec();
+        bytes.extend_from_slice(&width.to_be_bytes());
+        bytes.extend_from_slice(&height.to_be_bytes());
+        bytes.extend_from_slice(b"\x08\x06\x00\x00\x00");
+        bytes
+    }
+
+    #[test]
+    fn test_image_info() {
+        let info = image_info(&png(640, 480)).unwrap();
+        assert_eq!(
+            (info.format, info.width, info.height),
+            (ImageFormat::Png, 640, 480)
+        );
+
+        let gif = b"GIF89a\x20\x03\x58\x02\x00";
+        let info = image_info(gif).unwrap();
+        assert_eq!(
+            (info.format, info.width, info.height),
+            (ImageFormat::Gif, 800, 600)
+        );
+
+        // SOI, an APP1 segment to skip, then a baseline frame header
+        let jpeg = b"\xff\xd8\xff\xe1\x00\x04ab\xff\xc0\x00\x11\x08\x01\xe0\x02\x80\x03";
+        let info = image_info(jpeg).unwrap();
+        assert_eq!(
+            (info.format, info.width, info.height),
+            (ImageFormat::Jpeg, 640, 480)
+        );
+
+        let webp =
+            b"RIFF\x00\x00\x00\x00WEBPVP8X\x0a\x00\x00\x00\x00\x00\x00\x00\x7f\x02\x00\xdf\x01\x00";
+        let info = image_info(webp).unwrap();
+        assert_eq!(
+            (info.format, info.width, info.height),
+            (ImageFormat::WebP, 640, 480)
+        );
+
+        assert_eq!(image_info(&png(640, 480)[..20]), None);
+        assert_eq!(image_info(b"\xff\xd8\xff\xe1\x00\x04ab"), None);
+        assert_eq!(image_info(b"not an image"), None);
+    }
+
+    #[test]
+    fn test_exif_orientation() {
+        let jpeg = |tiff: &[u8]| {
+            let mut bytes = b"\xff\xd8\xff\xe0\x00\x04ab\xff\xe1".to_vec();
+            bytes.extend_from_slice(&(tiff.len() as u16 + 8).to_be_bytes());
+            bytes.extend_from_slice(b"Exif\0\0");
+            bytes.extend_from_slice(tiff);
+            bytes
+        };
+
+        // one IFD entry: tag 0x0112, type SHORT, count 1, value 6 or 8
+        let big = jpeg(b"MM\0\x2a\0\0\0\x08\0\x01\x01\x12\0\x03\0\0\0\x01\0\x06\0\0");
+        let little = jpeg(b"II\x2a\0\x08\0\0\0\x01\0\x12\x01\x03\0\x01\0\0\0\x08\0\0\0");
+        assert_eq!(exif_orientation(&big), 6);
+        assert_eq!(exif_orientation(&little), 8);
+
+        assert_eq!(exif_orientation(&big[..big.len() - 4]), 1);
+        assert_eq!(exif_orientation(&png(1, 1)), 1);
+    }
+
+    #[test]
+    fn test_constraints() {
+        let image = image_info(&png(20000, 20000)).unwrap();
+        let constraints = ImageConstraints {
+            max_width: Some(4096),
+            max_height: Some(4096),
+            ..ImageConstraints::default()
+        };
+        assert!(matches!(
+            constraints.check(&image),
+            Err(ValidationError(ImageDimensionError {
+                width: 20000,
+                height: 20000
+            }))
+        ));
+
+        let image = image_info(&png(1600, 400)).unwrap();
+        let constraints = ImageConstraints {
+            max_aspect_ratio: Some(2.0),
+            ..ImageConstraints::default()
+        };
+        assert!(matches!(
+            constraints.check(&image),
+            Err(ValidationError(ImageAspectRatioError { .. }))
+        ));
+
+        let constraints = ImageConstraints {
+            formats: vec![ImageFormat::Jpeg, ImageFormat::WebP],
+            ..ImageConstraints::default()
+        };
+        assert!(matches!(
+            constraints.check(&image),
+            Err(ValidationError(ImageFormatError(ImageFormat::Png)))
+        ));
+    }
+
+    #[test]
+    fn test_probe_across_chunks() {
+        let bytes = png(64, 32);
+        let mut probe = ImageProbe::default();
+        assert_eq!(probe.feed(&bytes[..10]).unwrap(), None);
+        assert!(probe.feed(&bytes[10..]).unwrap().is_some());
+        assert_eq!(probe.finish().unwrap().width, 64);
+
+        let mut probe = ImageProbe::default();
+        probe.feed(b"plain text").unwrap();
+        assert!(probe.finish().is_err());
+    }
+
+    #[test]
+    fn test_probe_resumes_jpeg_segments() {
+        let mut jpeg = b"\xff\xd8".to_vec();
+        for _ in 0..1000 {
+            jpeg.extend_from_slice(b"\xff\xe1\x00\x06meta");
+        }
+        jpeg.extend_from_slice(b"\xff\xff\xc0\x00\x11\x08\x01\xe0\x02\x80\x03");
+
+        let mut probe = ImageProbe::default();
+        for byte in &jpeg {
+            probe.feed(std::slice::from_ref(byte)).unwrap();
+        }
+
+        let image = probe.finish().unwrap();
+        assert_eq!((image.width, image.height), (640, 480));
+        assert!(probe.resume > 8000);
+    }
+}
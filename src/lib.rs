@@ -1,7 +1,51 @@
+mod captured;
+mod checksum;
+mod chunked;
+mod disposition;
+mod extension;
 mod file;
+mod form;
+mod image;
+mod metadata;
+mod mime;
+mod pipeline;
+#[cfg(feature = "image")]
+mod processor;
+mod progress;
 mod result;
+mod sanitize;
+mod scan;
+mod sink;
+mod sniff;
+mod storage;
+mod streamed;
+mod text;
+mod timeout;
+mod tus;
 mod uploader;
 
+pub use captured::CapturedFile;
+pub use checksum::{ChecksumAlgorithm, Checksums};
+pub use chunked::{ChunkAssembler, ChunkStatus};
+pub use extension::{extension_mime, file_extension, mime_extension};
 pub use file::FileInfo;
-pub use result::{MultipartError, MultipartValidationError};
-pub use uploader::{UploadData, Uploader};
+pub use form::{MultipartForm, MultipartFormConfig, DEFAULT_FORM_FILE_LIMIT};
+pub use image::{image_info, ImageConstraints, ImageFormat, ImageInfo};
+pub use metadata::strip_metadata;
+pub use pipeline::{ImagePipeline, ImageProcessor, ImageTarget, ImageVariant, Thumbnail};
+#[cfg(feature = "image")]
+pub use processor::ImageCrateProcessor;
+pub use progress::UploadProgress;
+pub use result::{FormError, MultipartError, MultipartValidationError};
+pub use sanitize::{random_storage_name, sanitize_filename};
+pub use scan::{ClamAvAddress, ClamAvScanner, ScanVerdict, Scanner};
+pub use sniff::{sniff_mime, SniffMode};
+pub use storage::{
+    LocalStorage, LocalWriter, MemoryStorage, MemoryWriter, S3Config, S3Storage, S3Writer,
+    StorageBackend, StorageWriter, StoredFile, S3_MIN_PART_SIZE,
+};
+pub use streamed::StreamedFile;
+pub use text::{TextFields, DEFAULT_TEXT_FIELD_LIMIT};
+pub use timeout::Timeouts;
+pub use tus::{TusServer, TusUpload};
+pub use uploader::{FileCountLimits, RequestLimits, UploadData, Uploader};
@@ -0,0 +1,520 @@
+use std::borrow::Cow;
+
+use ntex::util::Bytes;
+
+use crate::image::{tiff_orientation, ImageFormat};
+use crate::result::MultipartError::ValidationError;
+use crate::result::MultipartResult;
+use crate::result::MultipartValidationError::{ImageFormatError, InvalidImage};
+
+/// JPEG markers dropped when stripping: APP1 (EXIF, XMP), APP13 (IPTC) and comments;
+/// an EXIF orientation other than upright is written back in a segment of its own
+const JPEG_METADATA: &[u8] = &[0xe1, 0xed, 0xfe];
+
+/// PNG chunks dropped when stripping
+const PNG_METADATA: &[&[u8; 4]] = &[b"eXIf", b"tEXt", b"zTXt", b"iTXt", b"tIME"];
+
+/// WebP chunks dropped when stripping, with the VP8X flag announcing each
+const WEBP_METADATA: &[(&[u8; 4], u8)] = &[(b"EXIF", 0x08), (b"XMP ", 0x04)];
+
+/// Removes EXIF, XMP, GPS and textual metadata from a JPEG, PNG or WebP image
+/// without touching its pixel data; other content is returned unchanged.
+///
+/// Colour profiles and the EXIF orientation of JPEGs are kept, as dropping them
+/// changes how the image renders.
+pub fn strip_metadata(bytes: &[u8]) -> MultipartResult<Vec<u8>> {
+    let stripped = if bytes.starts_with(b"\xff\xd8") {
+        strip_jpeg(bytes)
+    } else if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+        strip_png(bytes)
+    } else if bytes.starts_with(b"RIFF") && bytes.get(8..12) == Some(b"WEBP") {
+        strip_webp(bytes)
+    } else {
+        return Ok(bytes.to_vec());
+    };
+
+    stripped.ok_or(ValidationError(InvalidImage))
+}
+
+fn strip_jpeg(bytes: &[u8]) -> Option<Vec<u8>> {
+    let mut out = bytes[..2].to_vec();
+    let mut pos = 2;
+
+    loop {
+        if *bytes.get(pos)? != 0xff {
+            return None;
+        }
+
+        let marker = *bytes.get(pos + 1)?;
+        match marker {
+            // fill byte before the actual marker
+            0xff => {
+                pos += 1;
+                continue;
+            }
+            0x01 | 0xd0..=0xd7 => {
+                out.extend_from_slice(&bytes[pos..pos + 2]);
+                pos += 2;
+                continue;
+            }
+            // start of scan: the entropy coded data and everything after it is kept as is
+            0xda | 0xd9 => {
+                out.extend_from_slice(&bytes[pos..]);
+                return Some(out);
+            }
+            _ => {}
+        }
+
+        let end =
+            pos + 2 + u16::from_be_bytes([*bytes.get(pos + 2)?, *bytes.get(pos + 3)?]) as usize;
+        if let Some(segment) = kept_segment(bytes.get(pos..end)?) {
+            out.extend_from_slice(&segment);
+        }
+        pos = end;
+    }
+}
+
+/// What is kept of a JPEG header segment: all of it, nothing, or for EXIF a
+/// minimal segment holding only the orientation
+fn kept_segment(segment: &[u8]) -> Option<Cow<'_, [u8]>> {
+    let marker = segment[1];
+    if !JPEG_METADATA.contains(&marker) {
+        return Some(Cow::Borrowed(segment));
+    }
+
+    let tiff = segment[4..]
+        .strip_prefix(b"Exif\0\0")
+        .filter(|_| marker == 0xe1)?;
+    let orientation = tiff_orientation(tiff).filter(|&orientation| orientation != 1)?;
+
+    // a single IFD entry: tag 0x0112, type SHORT, count 1, the padded value and no next IFD
+    let mut segment =
+        b"\xff\xe1\x00\x22Exif\0\0MM\0\x2a\0\0\0\x08\0\x01\x01\x12\0\x03\0\0\0\x01".to_vec();
+    segment.extend_from_slice(&orientation.to_be_bytes());
+    segment.extend_from_slice(&[0; 6]);
+    Some(Cow::Owned(segment))
+}
+
+fn strip_png(bytes: &[u8]) -> Option<Vec<u8>> {
+    let mut out = bytes[..8].to_vec();
+    let mut pos = 8;
+
+    while pos < bytes.len() {
+        let len = u32::from_be_bytes(bytes.get(pos..pos + 4)?.try_into().ok()?) as usize;
+        let kind = bytes.get(pos + 4..pos + 8)?;
+        // length, type, data and CRC
+        let end = pos + 12 + len;
+        let chunk = bytes.get(pos..end)?;
+
+        if !PNG_METADATA.iter().any(|k| k.as_slice() == kind) {
+            out.extend_from_slice(chunk);
+        }
+
+        if kind == b"IEND" {
+            return Some(out);
+        }
+        pos = end;
+    }
+
+    None
+}
+
+fn strip_webp(bytes: &[u8]) -> Option<Vec<u8>> {
+    let mut out = bytes[..12].to_vec();
+    let mut pos = 12;
+
+    while pos < bytes.len() {
+        let kind = bytes.get(pos..pos + 4)?;
+        let len = u32::from_le_bytes(bytes.get(pos + 4..pos + 8)?.try_into().ok()?) as usize;
+        if pos + 8 + len > bytes.len() {
+            return None;
+        }
+
+        // chunks are padded to an even size
+        let end = (pos + 8 + len + (len & 1)).min(bytes.len());
+        let chunk = &bytes[pos..end];
+
+        if !WEBP_METADATA.iter().any(|(k, _)| k.as_slice() == kind) {
+            let start = out.len();
+            out.extend_from_slice(chunk);
+
+            if kind == b"VP8X" {
+                let flags = out.get_mut(start + 8)?;
+                for (_, flag) in WEBP_METADATA {
+                    *flags &= !flag;
+                }
+            }
+        }
+        pos = end;
+    }
+
+    let riff_size = (out.len() - 8) as u32;
+    out[4..8].copy_from_slice(&riff_size.to_le_bytes());
+    Some(out)
+}
+
+/// Streaming counterpart of [`strip_metadata`] for captures written as they arrive.
+///
+/// JPEG header segments and PNG chunk headers are parsed as they come in and the
+/// image data passes through untouched. A WebP is held until complete, as its
+/// RIFF header records the final size, which is only allowed when the field's size
+/// is bounded; otherwise it is rejected.
+#[derive(Default)]
+pub(crate) struct MetadataStripper {
+    state: Stripping,
+    pending: Vec<u8>,
+    hold_webp: bool,
+}
+
+#[derive(Default, Clone, Copy)]
+enum Stripping {
+    /// Waiting for enough bytes to recognise the format
+    #[default]
+    Detect,
+    /// Within the JPEG header segments
+    Jpeg,
+    /// Between PNG chunks
+    Png,
+    /// Inside a PNG chunk, with the bytes left of its data and CRC
+    PngChunk {
+        keep: bool,
+        remaining: usize,
+        last: bool,
+    },
+    WebP,
+    Passthrough,
+    /// Past the PNG IEND chunk, where trailing bytes are dropped
+    Done,
+}
+
+impl MetadataStripper {
+    /// `hold_webp` allows buffering a whole WebP, for fields with an upper size
+    pub(crate) fn new(hold_webp: bool) -> Self {
+        Self {
+            hold_webp,
+            ..Self::default()
+        }
+    }
+
+    /// Strips `chunk`, pushing whatever can already be written to `out`
+    pub(crate) fn feed(&mut self, mut chunk: Bytes, out: &mut Vec<Bytes>) -> MultipartResult<()> {
+        while !chunk.is_empty() {
+            match self.state {
+                Stripping::Passthrough => {
+                    out.push(chunk);
+                    return Ok(());
+                }
+                Stripping::Done => return Ok(()),
+                Stripping::PngChunk {
+                    keep,
+                    remaining,
+                    last,
+                } => {
+                    let body = chunk.split_to(remaining.min(chunk.len()));
+                    self.state = match remaining - body.len() {
+                        0 if last => Stripping::Done,
+                        0 => Stripping::Png,
+                        remaining => Stripping::PngChunk {
+                            keep,
+                            remaining,
+                            last,
+                        },
+                    };
+                    if keep {
+                        out.push(body);
+                    }
+                }
+                Stripping::Png => {
+                    let wanted = (8 - self.pending.len()).min(chunk.len());
+                    self.pending.extend_from_slice(&chunk.split_to(wanted));
+                    if self.pending.len() < 8 {
+                        return Ok(());
+                    }
+
+                    let header = std::mem::take(&mut self.pending);
+                    let len = u32::from_be_bytes([header[0], header[1], header[2], header[3]]);
+                    let kind = &header[4..8];
+                    let keep = !PNG_METADATA.iter().any(|k| k.as_slice() == kind);
+                    self.state = Stripping::PngChunk {
+                        keep,
+                        remaining: len as usize + 4,
+                        last: kind == b"IEND",
+                    };
+                    if keep {
+                        out.push(Bytes::from(header));
+                    }
+                }
+                Stripping::Detect | Stripping::Jpeg | Stripping::WebP => {
+                    self.pending.extend_from_slice(&chunk);
+                    chunk = self.advance(out)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Flushes what is still held once the field is complete
+    pub(crate) fn finish(&mut self, out: &mut Vec<Bytes>) -> MultipartResult<()> {
+        match self.state {
+            Stripping::Detect | Stripping::WebP => {
+                let stripped = strip_metadata(&std::mem::take(&mut self.pending))?;
+                if !stripped.is_empty() {
+                    out.push(Bytes::from(stripped));
+                }
+                Ok(())
+            }
+            Stripping::Passthrough | Stripping::Done => Ok(()),
+            _ => Err(ValidationError(InvalidImage)),
+        }
+    }
+
+    /// Parses the buffered bytes, returning those left over once the state no
+    /// longer buffers
+    fn advance(&mut self, out: &mut Vec<Bytes>) -> MultipartResult<Bytes> {
+        if let Stripping::Detect = self.state {
+            let bytes = &self.pending;
+            if bytes.len() < 12 {
+                return Ok(Bytes::new());
+            }
+
+            if bytes.starts_with(b"\xff\xd8") {
+                out.push(Bytes::copy_from_slice(&bytes[..2]));
+                self.pending.drain(..2);
+                self.state = Stripping::Jpeg;
+            } else if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+                out.push(Bytes::copy_from_slice(&bytes[..8]));
+                self.pending.drain(..8);
+                self.state = Stripping::Png;
+                return Ok(Bytes::from(std::mem::take(&mut self.pending)));
+            } else if bytes.starts_with(b"RIFF") && &bytes[8..12] == b"WEBP" {
+                if !self.hold_webp {
+                    return Err(ValidationError(ImageFormatError(ImageFormat::WebP)));
+                }
+                self.state = Stripping::WebP;
+            } else {
+                self.state = Stripping::Passthrough;
+                return Ok(Bytes::from(std::mem::take(&mut self.pending)));
+            }
+        }
+
+        if let Stripping::Jpeg = self.state {
+            return self.advance_jpeg(out);
+        }
+
+        Ok(Bytes::new())
+    }
+
+    /// Same walk as [`strip_jpeg`], stopping at the first incomplete segment
+    fn advance_jpeg(&mut self, out: &mut Vec<Bytes>) -> MultipartResult<Bytes> {
+        let bytes = &self.pending;
+        let mut pos = 0;
+
+        let rest = loop {
+            let (Some(&prefix), Some(&marker)) = (bytes.get(pos), bytes.get(pos + 1)) else {
+                break None;
+            };
+            if prefix != 0xff {
+                return Err(ValidationError(InvalidImage));
+            }
+
+            match marker {
+                0xff => {
+                    pos += 1;
+                    continue;
+                }
+                0x01 | 0xd0..=0xd7 => {
+                    out.push(Bytes::copy_from_slice(&bytes[pos..pos + 2]));
+                    pos += 2;
+                    continue;
+                }
+                0xda | 0xd9 => break Some(Bytes::copy_from_slice(&bytes[pos..])),
+                _ => {}
+            }
+
+            let (Some(&high), Some(&low)) = (bytes.get(pos + 2), bytes.get(pos + 3)) else {
+                break None;
+            };
+            let end = pos + 2 + u16::from_be_bytes([high, low]) as usize;
+            let Some(segment) = bytes.get(pos..end) else {
+                break None;
+            };
+            if let Some(segment) = kept_segment(segment) {
+                out.push(Bytes::copy_from_slice(&segment));
+            }
+            pos = end;
+        };
+
+        match rest {
+            Some(rest) => {
+                self.pending = vec![];
+                self.state = Stripping::Passthrough;
+                Ok(rest)
+            }
+            None => {
+                self.pending.drain(..pos);
+                Ok(Bytes::new())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::exif_orientation;
+
+    #[test]
+    fn test_strip_jpeg() {
+        let mut jpeg = b"\xff\xd8".to_vec();
+        jpeg.extend_from_slice(b"\xff\xe0\x00\x06JFIF");
+        jpeg.extend_from_slice(b"\xff\xe1\x00\x0cExif\x00\x00GPS!");
+        jpeg.extend_from_slice(b"\xff\xfe\x00\x05hi!");
+        jpeg.extend_from_slice(b"\xff\xc0\x00\x04\x08\x00");
+        jpeg.extend_from_slice(b"\xff\xda\x00\x02pixels\xff\xd9");
+
+        let stripped = strip_metadata(&jpeg).unwrap();
+        let mut expected = b"\xff\xd8\xff\xe0\x00\x06JFIF\xff\xc0\x00\x04\x08\x00".to_vec();
+        expected.extend_from_slice(b"\xff\xda\x00\x02pixels\xff\xd9");
+        assert_eq!(stripped, expected);
+    }
+
+    #[test]
+    fn test_strip_png() {
+        let chunk = |kind: &[u8], data: &[u8]| {
+            let mut chunk = (data.len() as u32).to_be_bytes().to_vec();
+            chunk.extend_from_slice(kind);
+            chunk.extend_from_slice(data);
+            chunk.extend_from_slice(b"CRC!");
+            chunk
+        };
+
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        png.extend(chunk(b"IHDR", b"header"));
+        png.extend(chunk(b"eXIf", b"gps"));
+        png.extend(chunk(b"tEXt", b"Author\x00me"));
+        png.extend(chunk(b"IDAT", b"pixels"));
+        png.extend(chunk(b"IEND", b""));
+
+        let mut expected = b"\x89PNG\r\n\x1a\n".to_vec();
+        expected.extend(chunk(b"IHDR", b"header"));
+        expected.extend(chunk(b"IDAT", b"pixels"));
+        expected.extend(chunk(b"IEND", b""));
+        assert_eq!(strip_metadata(&png).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_strip_webp() {
+        let mut webp = b"RIFF\x00\x00\x00\x00WEBP".to_vec();
+        webp.extend_from_slice(b"VP8X\x0a\x00\x00\x00\x0c\x00\x00\x00\x00\x00\x00\x00\x00\x00");
+        webp.extend_from_slice(b"VP8L\x03\x00\x00\x00abc\x00");
+        webp.extend_from_slice(b"EXIF\x03\x00\x00\x00gps\x00");
+        webp.extend_from_slice(b"XMP \x02\x00\x00\x00<x");
+
+        let stripped = strip_metadata(&webp).unwrap();
+        assert_eq!(&stripped[4..8], &(stripped.len() as u32 - 8).to_le_bytes());
+        assert_eq!(stripped[20], 0x00);
+        assert_eq!(stripped.len(), 12 + 18 + 12);
+        assert!(!stripped.windows(4).any(|w| w == b"EXIF" || w == b"XMP "));
+    }
+
+    #[test]
+    fn test_stripper_matches_strip_metadata() {
+        let stream = |bytes: &[u8], size: usize| -> MultipartResult<Vec<u8>> {
+            let mut stripper = MetadataStripper::new(true);
+            let mut out = vec![];
+            for chunk in bytes.chunks(size) {
+                stripper.feed(Bytes::copy_from_slice(chunk), &mut out)?;
+            }
+            stripper.finish(&mut out)?;
+            Ok(out.concat())
+        };
+
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        for (kind, data) in [
+            (b"IHDR", &b"header"[..]),
+            (b"tEXt", b"Author\x00me"),
+            (b"IDAT", b"pixels"),
+            (b"IEND", b""),
+        ] {
+            png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+            png.extend_from_slice(kind);
+            png.extend_from_slice(data);
+            png.extend_from_slice(b"CRC!");
+        }
+        png.extend_from_slice(b"trailing");
+
+        let mut jpeg = b"\xff\xd8\xff\xe0\x00\x06JFIF".to_vec();
+        jpeg.extend_from_slice(b"\xff\xe1\x00\x0cExif\x00\x00GPS!\xff\xff");
+        jpeg.extend_from_slice(b"\xff\xda\x00\x02pixels\xff\xd9");
+
+        let mut webp = b"RIFF\x00\x00\x00\x00WEBP".to_vec();
+        webp.extend_from_slice(b"VP8L\x03\x00\x00\x00abc\x00EXIF\x03\x00\x00\x00gps\x00");
+
+        for bytes in [
+            &jpeg[..],
+            &oriented_jpeg(),
+            &png,
+            &webp,
+            b"%PDF-1.7",
+            b"plain text, long enough",
+        ] {
+            let expected = strip_metadata(bytes).unwrap();
+            for size in [1, 5, bytes.len()] {
+                assert_eq!(stream(bytes, size).unwrap(), expected);
+            }
+        }
+
+        assert!(stream(&jpeg[..20], 3).is_err());
+        assert!(stream(&png[..40], 3).is_err());
+        assert!(stream(b"\xff\xd8\xff\xe0\x00\x06JFIF\x00garbage", 4).is_err());
+    }
+
+    /// An orientation 6 JPEG whose EXIF also carries a GPS entry
+    fn oriented_jpeg() -> Vec<u8> {
+        let tiff = b"MM\0\x2a\0\0\0\x08\0\x02\x01\x12\0\x03\0\0\0\x01\0\x06\0\0\
+            \x88\x25\0\x04\0\0\0\x01\0\0\0\x26\0\0\0\0GPS!";
+        let mut jpeg = b"\xff\xd8\xff\xe1".to_vec();
+        jpeg.extend_from_slice(&(tiff.len() as u16 + 8).to_be_bytes());
+        jpeg.extend_from_slice(b"Exif\0\0");
+        jpeg.extend_from_slice(tiff);
+        jpeg.extend_from_slice(b"\xff\xc0\x00\x08\x08\x00\x02\x00\x04\x03");
+        jpeg.extend_from_slice(b"\xff\xda\x00\x02pixels\xff\xd9");
+        jpeg
+    }
+
+    #[test]
+    fn test_strip_keeps_jpeg_orientation() {
+        let jpeg = oriented_jpeg();
+        assert_eq!(exif_orientation(&jpeg), 6);
+
+        let stripped = strip_metadata(&jpeg).unwrap();
+        assert_eq!(exif_orientation(&stripped), 6);
+        assert!(!stripped.windows(4).any(|w| w == b"GPS!"));
+        assert!(stripped.len() < jpeg.len());
+
+        // an upright image needs no EXIF at all
+        let mut upright = jpeg.clone();
+        let at = upright.windows(2).position(|w| w == b"\0\x06").unwrap();
+        upright[at + 1] = 1;
+        let stripped = strip_metadata(&upright).unwrap();
+        assert!(!stripped.windows(4).any(|w| w == b"Exif"));
+    }
+
+    #[test]
+    fn test_stripper_rejects_unbounded_webp() {
+        let webp = b"RIFF\x00\x00\x00\x00WEBPVP8L\x03\x00\x00\x00abc\x00";
+        let mut out = vec![];
+        assert!(matches!(
+            MetadataStripper::new(false).feed(Bytes::from_static(webp), &mut out),
+            Err(ValidationError(ImageFormatError(ImageFormat::WebP)))
+        ));
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn test_strip_passthrough_and_malformed() {
+        assert_eq!(strip_metadata(b"%PDF-1.7").unwrap(), b"%PDF-1.7");
+        assert!(strip_metadata(b"\xff\xd8\xff\xe1\x00\xffshort").is_err());
+    }
+}
@@ -0,0 +1,80 @@
+use crate::result::MultipartError::ValidationError;
+use crate::result::MultipartResult;
+use crate::result::MultipartValidationError::InvalidMimeType;
+
+/// Checks `content_type` against the allow and deny lists; an empty allow-list accepts
+/// everything that is not denied
+pub(crate) fn validate_mime(
+    content_type: &str,
+    allowed: &[&str],
+    denied: &[&str],
+) -> MultipartResult<()> {
+    if !allowed.is_empty() && !allowed.iter().any(|p| mime_matches(p, content_type)) {
+        return Err(ValidationError(InvalidMimeType));
+    }
+
+    if denied.iter().any(|p| mime_matches(p, content_type)) {
+        return Err(ValidationError(InvalidMimeType));
+    }
+
+    Ok(())
+}
+
+/// Matches a content type against a pattern such as `image/png`, `image/*` or `*/*`,
+/// ignoring case and any parameters (`text/plain; charset=utf-8`)
+pub(crate) fn mime_matches(pattern: &str, content_type: &str) -> bool {
+    let pattern = essence(pattern);
+    let content_type = essence(content_type);
+
+    if pattern == "*" || pattern == "*/*" {
+        return true;
+    }
+
+    match pattern.strip_suffix("/*") {
+        Some(kind) => content_type
+            .split_once('/')
+            .is_some_and(|(ct_kind, _)| ct_kind == kind),
+        None => pattern == content_type,
+    }
+}
+
+/// Lowercased `type/subtype` without parameters
+pub(crate) fn essence(content_type: &str) -> String {
+    content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::result::MultipartError;
+
+    #[test]
+    fn test_mime_matches() {
+        assert!(mime_matches("image/png", "image/png"));
+        assert!(mime_matches("image/png", "IMAGE/PNG"));
+        assert!(mime_matches("text/plain", "text/plain; charset=utf-8"));
+        assert!(mime_matches("image/*", "image/webp"));
+        assert!(mime_matches("*/*", "application/pdf"));
+        assert!(!mime_matches("image/*", "application/pdf"));
+        assert!(!mime_matches("image/png", "image/jpeg"));
+    }
+
+    #[test]
+    fn test_validate_mime() {
+        assert!(validate_mime("image/png", &[], &[]).is_ok());
+        assert!(validate_mime("image/png", &["image/*"], &[]).is_ok());
+        assert!(matches!(
+            validate_mime("application/pdf", &["image/*"], &[]),
+            Err(MultipartError::ValidationError(InvalidMimeType))
+        ));
+        assert!(matches!(
+            validate_mime("image/svg+xml", &["image/*"], &["image/svg+xml"]),
+            Err(MultipartError::ValidationError(InvalidMimeType))
+        ));
+    }
+}
@@ -0,0 +1,286 @@
+use std::path::{Path, PathBuf};
+
+use ntex::util::Bytes;
+
+use crate::captured::CapturedFile;
+use crate::checksum::Checksums;
+use crate::extension::file_extension;
+use crate::file::FileInfo;
+use crate::image::{exif_orientation, image_info, ImageFormat, ImageInfo};
+use crate::result::MultipartError::ValidationError;
+use crate::result::MultipartResult;
+use crate::result::MultipartValidationError::InvalidImage;
+
+/// The pixel work behind an [`ImagePipeline`]: decoding, scaling and encoding.
+///
+/// With the opt-in `image` feature, [`ImageCrateProcessor`](crate::ImageCrateProcessor)
+/// implements it on the `image` crate. Encoders are expected to write no EXIF or
+/// other metadata.
+pub trait ImageProcessor {
+    /// Decodes `source`, turns it upright by its EXIF orientation, scales it to
+    /// exactly `target.width` x `target.height` and encodes it as `target.format`
+    fn transform(&self, source: &[u8], target: &ImageTarget) -> MultipartResult<Vec<u8>>;
+}
+
+/// What an [`ImageProcessor`] should turn its source into; the dimensions are
+/// those of the image as displayed, after its EXIF orientation is applied
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImageTarget {
+    pub width: u32,
+    pub height: u32,
+    pub format: ImageFormat,
+}
+
+/// A thumbnail to derive, fitted within `width` x `height` keeping the aspect ratio
+#[derive(Debug, Clone)]
+pub struct Thumbnail {
+    pub name: String,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Thumbnail {
+    pub fn new(name: &str, width: u32, height: u32) -> Self {
+        Self {
+            name: name.to_string(),
+            width,
+            height,
+        }
+    }
+}
+
+/// Steps run on a captured image by [`CapturedFile::process`]
+#[derive(Debug, Default, Clone)]
+pub struct ImagePipeline {
+    /// Bounds the image is scaled down to fit in, it is never scaled up
+    pub max_width: Option<u32>,
+    pub max_height: Option<u32>,
+    pub thumbnails: Vec<Thumbnail>,
+    /// Format the image and its thumbnails are encoded as, defaults to the original one
+    pub format: Option<ImageFormat>,
+    /// Re-encodes the image even when neither its size nor format change, dropping its metadata
+    pub strip_metadata: bool,
+}
+
+/// An image derived from an upload by an [`ImagePipeline`], such as a thumbnail
+#[derive(Debug, Clone)]
+pub struct ImageVariant {
+    pub name: String,
+    pub width: u32,
+    pub height: u32,
+    pub format: ImageFormat,
+    pub size: usize,
+}
+
+impl ImagePipeline {
+    pub(crate) fn run<P: ImageProcessor>(
+        &self,
+        captured: &mut CapturedFile,
+        processor: &P,
+    ) -> MultipartResult<()> {
+        // variants describe the last run only
+        captured.file.variants.clear();
+        captured.variants.clear();
+
+        let source = captured.bytes.concat();
+        let mut image = image_info(&source).ok_or(ValidationError(InvalidImage))?;
+        // orientations 5 to 8 turn the stored pixels a quarter, swapping the displayed sides
+        if exif_orientation(&source) >= 5 {
+            (image.width, image.height) = (image.height, image.width);
+        }
+        let format = self.format.unwrap_or(image.format);
+
+        for thumbnail in &self.thumbnails {
+            let (width, height) = fit(&image, Some(thumbnail.width), Some(thumbnail.height));
+            let target = ImageTarget {
+                width,
+                height,
+                format,
+            };
+
+            let bytes = processor.transform(&source, &target)?;
+            captured.file.variants.push(ImageVariant {
+                name: thumbnail.name.clone(),
+                width,
+                height,
+                format,
+                size: bytes.len(),
+            });
+            captured.variants.push(Bytes::from(bytes));
+        }
+
+        let (width, height) = fit(&image, self.max_width, self.max_height);
+        let unchanged = (width, height) == (image.width, image.height) && format == image.format;
+        if unchanged && !self.strip_metadata {
+            return Ok(());
+        }
+
+        let target = ImageTarget {
+            width,
+            height,
+            format,
+        };
+
+        let bytes = processor.transform(&source, &target)?;
+        let file = &mut captured.file;
+        file.size = bytes.len();
+        file.name = renamed(file, format);
+        file.content_type = format.mime().to_string();
+        file.detected_content_type = file
+            .detected_content_type
+            .as_ref()
+            .map(|_| file.content_type.clone());
+        file.extension = Some(format.extension().to_string());
+        file.image = Some(ImageInfo {
+            format,
+            width,
+            height,
+        });
+        // digests taken during capture describe the original bytes
+        file.checksums = Checksums::default();
+        captured.bytes = vec![Bytes::from(bytes)];
+
+        Ok(())
+    }
+}
+
+/// Dimensions of `image` scaled down to fit the bounds, keeping its aspect ratio
+fn fit(image: &ImageInfo, max_width: Option<u32>, max_height: Option<u32>) -> (u32, u32) {
+    let scale = |max: Option<u32>, value: u32| max.map_or(1.0, |max| max as f64 / value as f64);
+    let scale = scale(max_width, image.width)
+        .min(scale(max_height, image.height))
+        .min(1.0);
+
+    let width = (image.width as f64 * scale).round().max(1.0);
+    let height = (image.height as f64 * scale).round().max(1.0);
+    (width as u32, height as u32)
+}
+
+/// The file's name with its extension replaced by the one of `format`, as in
+/// `photo.webp` for `photo.jpg`
+fn renamed(file: &FileInfo, format: ImageFormat) -> String {
+    if file.name.is_empty() {
+        return String::new();
+    }
+
+    let stem = file_extension(&file.name).map_or(file.name.as_str(), |extension| {
+        &file.name[..file.name.len() - extension.len() - 1]
+    });
+    format!("{}.{}", stem, format.extension())
+}
+
+/// Path a variant is saved at next to `path`, as in `photo_thumb.webp` for `photo.jpg`
+pub(crate) fn variant_path(path: &Path, variant: &ImageVariant) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = format!("{}_{}.{}", stem, variant.name, variant.format.extension());
+    path.with_file_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Pretends to encode by emitting a PNG header with the target dimensions
+    struct HeaderOnly;
+
+    impl ImageProcessor for HeaderOnly {
+        fn transform(&self, _source: &[u8], target: &ImageTarget) -> MultipartResult<Vec<u8>> {
+            Ok(png(target.width, target.height))
+        }
+    }
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let mut bytes = b"\x89PNG\r\n\x1a\n\x00\x00\x00\x0dIHDR".to_vec();
+        bytes.extend_from_slice(&width.to_be_bytes());
+        bytes.extend_from_slice(&height.to_be_bytes());
+        bytes
+    }
+
+    fn captured(width: u32, height: u32) -> CapturedFile {
+        let info = FileInfo {
+            name: "photo.png".to_string(),
+            content_type: "image/png".to_string(),
+            ..FileInfo::default()
+        };
+        CapturedFile::new(info, vec![Bytes::from(png(width, height))])
+    }
+
+    #[test]
+    fn test_fit() {
+        let image = image_info(&png(4000, 3000)).unwrap();
+        assert_eq!(fit(&image, Some(1000), Some(1000)), (1000, 750));
+        assert_eq!(fit(&image, None, Some(300)), (400, 300));
+        assert_eq!(fit(&image, Some(8000), None), (4000, 3000));
+    }
+
+    #[test]
+    fn test_resize_and_thumbnails() {
+        let mut file = captured(4000, 3000);
+        let pipeline = ImagePipeline {
+            max_width: Some(2000),
+            max_height: Some(2000),
+            thumbnails: vec![Thumbnail::new("small", 200, 200)],
+            ..ImagePipeline::default()
+        };
+
+        file.process(&pipeline, &HeaderOnly).unwrap();
+
+        let image = file.file().image.unwrap();
+        assert_eq!((image.width, image.height), (2000, 1500));
+        assert_eq!(file.file().size, file.bytes()[0].len());
+
+        let (variant, bytes) = file.variant("small").unwrap();
+        assert_eq!((variant.width, variant.height), (200, 150));
+        assert_eq!(image_info(bytes).unwrap().width, 200);
+    }
+
+    #[test]
+    fn test_untouched_when_within_bounds() {
+        let mut file = captured(800, 600);
+        let pipeline = ImagePipeline {
+            max_width: Some(2000),
+            ..ImagePipeline::default()
+        };
+
+        file.process(&pipeline, &HeaderOnly).unwrap();
+        assert_eq!(file.bytes()[0], png(800, 600));
+        assert!(file.file().variants.is_empty());
+    }
+
+    #[test]
+    fn test_reencoding_renames_and_reruns_replace_variants() {
+        let mut file = captured(4000, 3000);
+        file.file.name = "Holiday.Photo.PNG".to_string();
+        file.file.detected_content_type = Some("image/png".to_string());
+        let pipeline = ImagePipeline {
+            thumbnails: vec![Thumbnail::new("small", 200, 200)],
+            format: Some(ImageFormat::WebP),
+            ..ImagePipeline::default()
+        };
+
+        file.process(&pipeline, &HeaderOnly).unwrap();
+        file.process(&pipeline, &HeaderOnly).unwrap();
+
+        assert_eq!(file.file().name, "Holiday.Photo.webp");
+        assert_eq!(file.file().extension.as_deref(), Some("webp"));
+        assert_eq!(file.file().inferred_extension().as_deref(), Some("webp"));
+        assert_eq!(file.file().variants.len(), 1);
+        assert_eq!(file.variants.len(), 1);
+    }
+
+    #[test]
+    fn test_variant_path() {
+        let variant = ImageVariant {
+            name: "thumb".to_string(),
+            width: 1,
+            height: 1,
+            format: ImageFormat::WebP,
+            size: 0,
+        };
+
+        assert_eq!(
+            variant_path(Path::new("/uploads/photo.jpg"), &variant),
+            PathBuf::from("/uploads/photo_thumb.webp")
+        );
+    }
+}
@@ -0,0 +1,243 @@
+use std::io::{Cursor, Error};
+
+use ::image::codecs::jpeg::JpegEncoder;
+use ::image::imageops::FilterType;
+use ::image::metadata::Orientation;
+use ::image::{DynamicImage, ImageError, ImageReader, ImageResult};
+
+use crate::image::{exif_orientation, image_info, ImageFormat, ImageInfo};
+use crate::pipeline::{ImageProcessor, ImageTarget};
+use crate::result::MultipartError::{IoError, ValidationError};
+use crate::result::MultipartValidationError::{
+    ImageDimensionError, ImageFormatError, InvalidImage,
+};
+use crate::result::{MultipartError, MultipartResult};
+
+/// An [`ImageProcessor`] built on the `image` crate, enabled by the `image` feature.
+///
+/// It decodes JPEG, PNG, GIF (its first frame), WebP and BMP, turns JPEGs upright by
+/// their EXIF orientation, scales with a triangle filter and encodes any
+/// [`ImageFormat`], WebP losslessly. No metadata is written.
+#[derive(Debug, Clone)]
+pub struct ImageCrateProcessor {
+    quality: u8,
+    max_pixels: u64,
+}
+
+impl Default for ImageCrateProcessor {
+    fn default() -> Self {
+        Self {
+            quality: 85,
+            max_pixels: 40_000_000,
+        }
+    }
+}
+
+impl ImageCrateProcessor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// JPEG quality from 1 to 100, 85 by default
+    pub fn quality(mut self, quality: u8) -> Self {
+        self.quality = quality.clamp(1, 100);
+        self
+    }
+
+    /// Largest source image decoded, in pixels; bigger ones are rejected from their
+    /// header before any pixel memory is allocated. 40 megapixels by default.
+    pub fn max_pixels(mut self, max_pixels: u64) -> Self {
+        self.max_pixels = max_pixels;
+        self
+    }
+
+    fn encode(&self, image: &DynamicImage, format: ImageFormat) -> ImageResult<Vec<u8>> {
+        let mut bytes = Cursor::new(vec![]);
+        match format {
+            ImageFormat::Jpeg => {
+                let encoder = JpegEncoder::new_with_quality(&mut bytes, self.quality);
+                DynamicImage::ImageRgb8(image.to_rgb8()).write_with_encoder(encoder)?;
+            }
+            format => {
+                DynamicImage::ImageRgba8(image.to_rgba8()).write_to(&mut bytes, codec(format))?
+            }
+        }
+
+        Ok(bytes.into_inner())
+    }
+}
+
+impl ImageProcessor for ImageCrateProcessor {
+    fn transform(&self, source: &[u8], target: &ImageTarget) -> MultipartResult<Vec<u8>> {
+        let image = image_info(source).ok_or(ValidationError(InvalidImage))?;
+        if image.width as u64 * image.height as u64 > self.max_pixels {
+            return Err(ValidationError(ImageDimensionError {
+                width: image.width,
+                height: image.height,
+            }));
+        }
+
+        let mut decoded = ImageReader::with_format(Cursor::new(source), codec(image.format))
+            .decode()
+            .map_err(|err| decode_error(err, &image))?;
+        if let Some(orientation) = Orientation::from_exif(exif_orientation(source) as u8) {
+            decoded.apply_orientation(orientation);
+        }
+
+        let scaled = decoded.resize_exact(target.width, target.height, FilterType::Triangle);
+        self.encode(&scaled, target.format)
+            .map_err(|err| match err {
+                ImageError::IoError(err) => IoError(err),
+                err => IoError(Error::other(err)),
+            })
+    }
+}
+
+fn codec(format: ImageFormat) -> ::image::ImageFormat {
+    match format {
+        ImageFormat::Png => ::image::ImageFormat::Png,
+        ImageFormat::Jpeg => ::image::ImageFormat::Jpeg,
+        ImageFormat::Gif => ::image::ImageFormat::Gif,
+        ImageFormat::WebP => ::image::ImageFormat::WebP,
+        ImageFormat::Bmp => ::image::ImageFormat::Bmp,
+    }
+}
+
+/// Sources are read from memory, so anything but an unsupported variant or a blown
+/// allocation limit means the image itself is broken
+fn decode_error(err: ImageError, image: &ImageInfo) -> MultipartError {
+    match err {
+        ImageError::Unsupported(_) => ValidationError(ImageFormatError(image.format)),
+        ImageError::Limits(_) => ValidationError(ImageDimensionError {
+            width: image.width,
+            height: image.height,
+        }),
+        _ => ValidationError(InvalidImage),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::image::{Rgba, RgbaImage};
+    use ntex::util::Bytes;
+
+    use super::*;
+    use crate::captured::CapturedFile;
+    use crate::file::FileInfo;
+    use crate::pipeline::{ImagePipeline, Thumbnail};
+
+    const FORMATS: [ImageFormat; 5] = [
+        ImageFormat::Jpeg,
+        ImageFormat::Png,
+        ImageFormat::Gif,
+        ImageFormat::WebP,
+        ImageFormat::Bmp,
+    ];
+
+    /// A `width` x `height` image, red on its left half and blue on its right half
+    fn halves(width: u32, height: u32, format: ImageFormat) -> Vec<u8> {
+        let image = RgbaImage::from_fn(width, height, |x, _| match x < width / 2 {
+            true => Rgba([255, 0, 0, 255]),
+            false => Rgba([0, 0, 255, 255]),
+        });
+
+        ImageCrateProcessor::new()
+            .quality(95)
+            .encode(&DynamicImage::ImageRgba8(image), format)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_transform_between_formats() {
+        let processor = ImageCrateProcessor::new();
+        for source in FORMATS.map(|format| halves(16, 16, format)) {
+            for format in FORMATS {
+                let target = ImageTarget {
+                    width: 8,
+                    height: 4,
+                    format,
+                };
+
+                let bytes = processor.transform(&source, &target).unwrap();
+                let info = image_info(&bytes).unwrap();
+                assert_eq!((info.format, info.width, info.height), (format, 8, 4));
+            }
+        }
+    }
+
+    #[test]
+    fn test_transform_rejects_invalid_input() {
+        let processor = ImageCrateProcessor::new();
+        let target = ImageTarget {
+            width: 1,
+            height: 1,
+            format: ImageFormat::Png,
+        };
+
+        assert!(matches!(
+            processor.transform(b"text", &target),
+            Err(ValidationError(InvalidImage))
+        ));
+
+        let jpeg = halves(16, 16, ImageFormat::Jpeg);
+        assert!(matches!(
+            processor.transform(&jpeg[..jpeg.len() / 2], &target),
+            Err(ValidationError(InvalidImage))
+        ));
+        assert!(matches!(
+            ImageCrateProcessor::new()
+                .max_pixels(100)
+                .transform(&jpeg, &target),
+            Err(ValidationError(ImageDimensionError {
+                width: 16,
+                height: 16
+            }))
+        ));
+    }
+
+    #[test]
+    fn test_pipeline_applies_exif_orientation() {
+        // a 4x2 image stored sideways, displayed as 2x4 once rotated clockwise
+        let stored = halves(4, 2, ImageFormat::Jpeg);
+
+        let mut exif =
+            b"Exif\0\0MM\0\x2a\0\0\0\x08\0\x01\x01\x12\0\x03\0\0\0\x01\0\x06\0\0".to_vec();
+        exif.extend_from_slice(&[0; 4]);
+        let mut bytes = stored[..2].to_vec();
+        bytes.extend_from_slice(&[0xff, 0xe1]);
+        bytes.extend_from_slice(&(exif.len() as u16 + 2).to_be_bytes());
+        bytes.extend_from_slice(&exif);
+        bytes.extend_from_slice(&stored[2..]);
+        assert_eq!(exif_orientation(&bytes), 6);
+
+        let info = FileInfo {
+            content_type: "image/jpeg".to_string(),
+            ..FileInfo::default()
+        };
+        let mut file = CapturedFile::new(info, vec![Bytes::from(bytes)]);
+        // stripping keeps the orientation the pipeline relies on
+        file.strip_metadata().unwrap();
+        assert_eq!(exif_orientation(&file.bytes()[0]), 6);
+        let pipeline = ImagePipeline {
+            thumbnails: vec![Thumbnail::new("small", 1, 2)],
+            format: Some(ImageFormat::Png),
+            strip_metadata: true,
+            ..ImagePipeline::default()
+        };
+        file.process(&pipeline, &ImageCrateProcessor::new())
+            .unwrap();
+
+        let upright = ::image::load_from_memory(&file.bytes()[0])
+            .unwrap()
+            .to_rgba8();
+        assert_eq!(upright.dimensions(), (2, 4));
+        let (variant, thumbnail) = file.variant("small").unwrap();
+        assert_eq!((variant.width, variant.height), (1, 2));
+        assert_eq!(image_info(thumbnail).unwrap().height, 2);
+
+        // turning moves red to the top and blue to the bottom
+        let (top, bottom) = (upright.get_pixel(0, 0), upright.get_pixel(0, 3));
+        assert!(top[0] > 200 && top[2] < 60, "{:?}", top);
+        assert!(bottom[0] < 60 && bottom[2] > 200, "{:?}", bottom);
+    }
+}
@@ -0,0 +1,66 @@
+use tokio::sync::watch;
+
+/// Snapshot of how far an upload got, reported as each chunk of a part arrives
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UploadProgress {
+    /// Name of the part being read
+    pub field: String,
+    /// Bytes received for `field` so far
+    pub field_bytes: usize,
+    /// Part body bytes received across the whole request so far
+    pub received: usize,
+    /// Size of the request body, when the client announced it
+    pub content_length: Option<usize>,
+}
+
+impl UploadProgress {
+    /// Share of the request body received, from `0.0` to `100.0`; `None` when the
+    /// `Content-Length` is unknown.
+    ///
+    /// Boundaries and part headers are not counted, so the value stays slightly
+    /// below `100.0` until the upload completes.
+    pub fn percentage(&self) -> Option<f64> {
+        self.content_length
+            .filter(|len| *len > 0)
+            .map(|len| (self.received as f64 / len as f64 * 100.0).min(100.0))
+    }
+}
+
+/// A listener registered through [`Uploader::on_progress`](crate::Uploader::on_progress)
+/// or [`Uploader::progress_channel`](crate::Uploader::progress_channel)
+pub(crate) enum ProgressHook {
+    Callback(Box<dyn FnMut(&UploadProgress)>),
+    Channel(watch::Sender<UploadProgress>),
+}
+
+impl ProgressHook {
+    pub(crate) fn notify(&mut self, progress: &UploadProgress) {
+        match self {
+            ProgressHook::Callback(callback) => callback(progress),
+            // keeps the latest value even while no receiver is subscribed
+            ProgressHook::Channel(sender) => {
+                sender.send_replace(progress.clone());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_percentage() {
+        let mut progress = UploadProgress {
+            received: 250,
+            ..UploadProgress::default()
+        };
+        assert_eq!(progress.percentage(), None);
+
+        progress.content_length = Some(1000);
+        assert_eq!(progress.percentage(), Some(25.0));
+
+        progress.received = 1200;
+        assert_eq!(progress.percentage(), Some(100.0));
+    }
+}
@@ -1,5 +1,12 @@
+use std::fmt::{Display, Formatter};
 use std::io::Error;
 
+use ntex::http::StatusCode;
+use ntex::web::{DefaultError, HttpRequest, HttpResponse, WebResponseError};
+use serde::Serialize;
+
+use crate::image::ImageFormat;
+
 pub type MultipartResult<T> = Result<T, MultipartError>;
 
 #[derive(Debug)]
@@ -9,7 +16,29 @@ pub enum MultipartError {
     InvalidContentType,
     InvalidContentDisposition,
     NtexError(ntex_multipart::MultipartError),
+    /// The stream failed while a field was being read, after `received` bytes of it
+    Incomplete {
+        received: usize,
+        source: ntex_multipart::MultipartError,
+    },
     ValidationError(MultipartValidationError),
+    FormError(FormError),
+    /// A remote storage backend rejected or failed a request
+    StorageError(String),
+    /// A malware scanner matched the named signature
+    Infected {
+        signature: String,
+    },
+    ScanError(String),
+    /// No data arrived within [`Timeouts::idle`](crate::Timeouts::idle)
+    IdleTimeout,
+    /// Reading the request took longer than [`Timeouts::deadline`](crate::Timeouts::deadline)
+    DeadlineExceeded,
+    /// The client sent slower than [`Timeouts::min_rate`](crate::Timeouts::min_rate),
+    /// averaging `rate` bytes per second
+    TooSlow {
+        rate: usize,
+    },
 }
 
 #[derive(Debug)]
@@ -17,6 +46,291 @@ pub enum MultipartValidationError {
     LowerSizeError,
     UpperSizeError,
     InvalidMimeType,
+    ContentTypeMismatch,
+    ExtensionError,
+    ChecksumMismatch,
+    TextFieldSizeError,
+    InvalidTextField,
+    TooManyParts,
+    TotalSizeError,
+    HeaderSizeError,
+    TotalTextSizeError,
+    TooFewFiles,
+    TooManyFiles,
+    CombinedSizeError,
+    InvalidImage,
+    ImageFormatError(ImageFormat),
+    ImageDimensionError { width: u32, height: u32 },
+    ImageAspectRatioError { width: u32, height: u32 },
+}
+
+impl MultipartError {
+    /// Machine readable identifier used in JSON error responses
+    pub fn code(&self) -> &'static str {
+        match self {
+            MultipartError::IoError(_) => "io_error",
+            MultipartError::NotUploaded => "not_uploaded",
+            MultipartError::InvalidContentType => "invalid_content_type",
+            MultipartError::InvalidContentDisposition => "invalid_content_disposition",
+            MultipartError::NtexError(_) => "malformed_multipart",
+            MultipartError::Incomplete { .. } => "incomplete_upload",
+            MultipartError::ValidationError(err) => err.code(),
+            MultipartError::FormError(_) => "invalid_form",
+            MultipartError::StorageError(_) => "storage_error",
+            MultipartError::Infected { .. } => "infected",
+            MultipartError::ScanError(_) => "scan_error",
+            MultipartError::IdleTimeout => "idle_timeout",
+            MultipartError::DeadlineExceeded => "deadline_exceeded",
+            MultipartError::TooSlow { .. } => "upload_too_slow",
+        }
+    }
+
+    /// Message safe to send to clients: errors wrapping OS, storage, scanner or parser
+    /// errors get a generic text, server errors are logged with their detail instead
+    fn public_message(&self) -> String {
+        match self {
+            MultipartError::IoError(_) | MultipartError::StorageError(_) => {
+                "failed to store upload"
+            }
+            MultipartError::ScanError(_) => "failed to scan upload",
+            MultipartError::NtexError(_) => "malformed multipart payload",
+            MultipartError::Incomplete { received, .. } => {
+                return format!("upload interrupted after {} bytes", received);
+            }
+            _ => return self.to_string(),
+        }
+        .to_string()
+    }
+}
+
+impl MultipartValidationError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            MultipartValidationError::LowerSizeError => "file_too_small",
+            MultipartValidationError::UpperSizeError => "file_too_large",
+            MultipartValidationError::InvalidMimeType => "invalid_mime_type",
+            MultipartValidationError::ContentTypeMismatch => "content_type_mismatch",
+            MultipartValidationError::ExtensionError => "invalid_extension",
+            MultipartValidationError::ChecksumMismatch => "checksum_mismatch",
+            MultipartValidationError::TextFieldSizeError => "text_field_too_large",
+            MultipartValidationError::InvalidTextField => "invalid_text_field",
+            MultipartValidationError::TooManyParts => "too_many_parts",
+            MultipartValidationError::TotalSizeError => "request_too_large",
+            MultipartValidationError::HeaderSizeError => "part_headers_too_large",
+            MultipartValidationError::TotalTextSizeError => "text_fields_too_large",
+            MultipartValidationError::TooFewFiles => "too_few_files",
+            MultipartValidationError::TooManyFiles => "too_many_files",
+            MultipartValidationError::CombinedSizeError => "files_too_large",
+            MultipartValidationError::InvalidImage => "invalid_image",
+            MultipartValidationError::ImageFormatError(_) => "invalid_image_format",
+            MultipartValidationError::ImageDimensionError { .. } => "invalid_image_dimensions",
+            MultipartValidationError::ImageAspectRatioError { .. } => "invalid_image_aspect_ratio",
+        }
+    }
+
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            MultipartValidationError::UpperSizeError
+            | MultipartValidationError::TextFieldSizeError
+            | MultipartValidationError::TooManyParts
+            | MultipartValidationError::TotalSizeError
+            | MultipartValidationError::HeaderSizeError
+            | MultipartValidationError::TotalTextSizeError
+            | MultipartValidationError::CombinedSizeError => StatusCode::PAYLOAD_TOO_LARGE,
+            MultipartValidationError::InvalidMimeType
+            | MultipartValidationError::ContentTypeMismatch
+            | MultipartValidationError::ExtensionError
+            | MultipartValidationError::ImageFormatError(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            MultipartValidationError::LowerSizeError
+            | MultipartValidationError::ChecksumMismatch
+            | MultipartValidationError::TooFewFiles
+            | MultipartValidationError::TooManyFiles
+            | MultipartValidationError::InvalidImage
+            | MultipartValidationError::ImageDimensionError { .. }
+            | MultipartValidationError::ImageAspectRatioError { .. }
+            | MultipartValidationError::InvalidTextField => StatusCode::UNPROCESSABLE_ENTITY,
+        }
+    }
+}
+
+impl Display for MultipartError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MultipartError::IoError(err) => write!(f, "failed to store upload: {}", err),
+            MultipartError::NotUploaded => f.write_str("expected file was not uploaded"),
+            MultipartError::InvalidContentType => f.write_str("invalid or missing content type"),
+            MultipartError::InvalidContentDisposition => {
+                f.write_str("invalid or missing content disposition")
+            }
+            MultipartError::NtexError(err) => write!(f, "malformed multipart payload: {}", err),
+            MultipartError::Incomplete { received, source } => {
+                write!(f, "upload interrupted after {} bytes: {}", received, source)
+            }
+            MultipartError::ValidationError(err) => Display::fmt(err, f),
+            MultipartError::FormError(err) => Display::fmt(err, f),
+            MultipartError::StorageError(err) => write!(f, "failed to store upload: {}", err),
+            MultipartError::Infected { signature } => {
+                write!(f, "uploaded file is infected: {}", signature)
+            }
+            MultipartError::ScanError(err) => write!(f, "failed to scan upload: {}", err),
+            MultipartError::IdleTimeout => f.write_str("upload stalled waiting for data"),
+            MultipartError::DeadlineExceeded => f.write_str("upload took too long"),
+            MultipartError::TooSlow { rate } => {
+                write!(f, "upload too slow at {} bytes per second", rate)
+            }
+        }
+    }
+}
+
+impl Display for MultipartValidationError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            MultipartValidationError::ImageFormatError(format) => {
+                return write!(f, "image format {} is not allowed", format.mime());
+            }
+            MultipartValidationError::ImageDimensionError { width, height } => {
+                return write!(f, "image dimensions {}x{} are not allowed", width, height);
+            }
+            MultipartValidationError::ImageAspectRatioError { width, height } => {
+                return write!(
+                    f,
+                    "image aspect ratio of {}x{} is not allowed",
+                    width, height
+                );
+            }
+            MultipartValidationError::LowerSizeError => "uploaded file is too small",
+            MultipartValidationError::UpperSizeError => "uploaded file is too large",
+            MultipartValidationError::InvalidMimeType => "uploaded file type is not allowed",
+            MultipartValidationError::ContentTypeMismatch => {
+                "uploaded file content does not match its declared type"
+            }
+            MultipartValidationError::ExtensionError => "uploaded file extension is not allowed",
+            MultipartValidationError::ChecksumMismatch => {
+                "uploaded file does not match the supplied checksum"
+            }
+            MultipartValidationError::TextFieldSizeError => "text field is too large",
+            MultipartValidationError::InvalidTextField => "text field has an invalid value",
+            MultipartValidationError::TooManyParts => "request has too many parts",
+            MultipartValidationError::TotalSizeError => "request body is too large",
+            MultipartValidationError::HeaderSizeError => "part headers are too large",
+            MultipartValidationError::TotalTextSizeError => "text fields are too large in total",
+            MultipartValidationError::TooFewFiles => "too few files were uploaded",
+            MultipartValidationError::TooManyFiles => "too many files were uploaded",
+            MultipartValidationError::CombinedSizeError => "uploaded files are too large in total",
+            MultipartValidationError::InvalidImage => "uploaded file is not a valid image",
+        })
+    }
+}
+
+impl std::error::Error for MultipartError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            MultipartError::IoError(err) => Some(err),
+            MultipartError::NtexError(err) => Some(err),
+            MultipartError::Incomplete { source, .. } => Some(source),
+            MultipartError::ValidationError(err) => Some(err),
+            MultipartError::FormError(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl std::error::Error for MultipartValidationError {}
+
+#[derive(Serialize)]
+struct ErrorBody<'a> {
+    code: &'a str,
+    message: String,
+}
+
+impl WebResponseError<DefaultError> for MultipartError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            MultipartError::IoError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            MultipartError::ValidationError(err) => err.status_code(),
+            MultipartError::FormError(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            MultipartError::StorageError(_) | MultipartError::ScanError(_) => {
+                StatusCode::BAD_GATEWAY
+            }
+            MultipartError::Infected { .. } => StatusCode::UNPROCESSABLE_ENTITY,
+            MultipartError::IdleTimeout
+            | MultipartError::DeadlineExceeded
+            | MultipartError::TooSlow { .. } => StatusCode::REQUEST_TIMEOUT,
+            MultipartError::NotUploaded
+            | MultipartError::InvalidContentType
+            | MultipartError::InvalidContentDisposition
+            | MultipartError::NtexError(_)
+            | MultipartError::Incomplete { .. } => StatusCode::BAD_REQUEST,
+        }
+    }
+
+    fn error_response(&self, _: &HttpRequest) -> HttpResponse {
+        let status = WebResponseError::<DefaultError>::status_code(self);
+        if status.is_server_error() {
+            log::error!("upload failed: {}", self);
+        }
+
+        HttpResponse::build(status).json(&ErrorBody {
+            code: self.code(),
+            message: self.public_message(),
+        })
+    }
+}
+
+/// Errors raised while deserializing a [`crate::MultipartForm`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FormError {
+    MissingField(String),
+    UnknownField(String),
+    InvalidValue { field: String, message: String },
+    Custom(String),
+}
+
+impl FormError {
+    pub(crate) fn with_field(self, field: &str) -> Self {
+        match self {
+            FormError::Custom(message) => FormError::InvalidValue {
+                field: field.to_string(),
+                message,
+            },
+            err => err,
+        }
+    }
+}
+
+impl Display for FormError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FormError::MissingField(field) => write!(f, "missing field `{}`", field),
+            FormError::UnknownField(field) => write!(f, "unknown field `{}`", field),
+            FormError::InvalidValue { field, message } => {
+                write!(f, "invalid value for field `{}`: {}", field, message)
+            }
+            FormError::Custom(message) => f.write_str(message),
+        }
+    }
+}
+
+impl std::error::Error for FormError {}
+
+impl serde::de::Error for FormError {
+    fn custom<T: Display>(msg: T) -> Self {
+        FormError::Custom(msg.to_string())
+    }
+
+    fn unknown_field(field: &str, _expected: &'static [&'static str]) -> Self {
+        FormError::UnknownField(field.to_string())
+    }
+
+    fn missing_field(field: &'static str) -> Self {
+        FormError::MissingField(field.to_string())
+    }
+}
+
+impl From<FormError> for MultipartError {
+    fn from(value: FormError) -> Self {
+        MultipartError::FormError(value)
+    }
 }
 
 impl From<Error> for MultipartError {
@@ -24,3 +338,76 @@ impl From<Error> for MultipartError {
         MultipartError::IoError(value)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use ntex::http::body::Body;
+    use ntex::web::test::TestRequest;
+
+    use super::*;
+
+    fn status_of(err: MultipartError) -> StatusCode {
+        WebResponseError::<DefaultError>::status_code(&err)
+    }
+
+    #[test]
+    fn test_status_codes() {
+        let upper = MultipartError::ValidationError(MultipartValidationError::UpperSizeError);
+        let mime = MultipartError::ValidationError(MultipartValidationError::InvalidMimeType);
+
+        assert_eq!(status_of(upper), StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(status_of(mime), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        assert_eq!(
+            status_of(MultipartError::InvalidContentDisposition),
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            status_of(MultipartError::TooSlow { rate: 1 }),
+            StatusCode::REQUEST_TIMEOUT
+        );
+    }
+
+    #[test]
+    fn test_boxed_error() {
+        fn fails() -> Result<(), Box<dyn std::error::Error>> {
+            Err(MultipartError::NotUploaded)?
+        }
+
+        assert_eq!(
+            fails().unwrap_err().to_string(),
+            "expected file was not uploaded"
+        );
+    }
+
+    #[test]
+    fn test_json_error_response() {
+        let req = TestRequest::default().to_http_request();
+        let err = MultipartError::ValidationError(MultipartValidationError::UpperSizeError);
+        let resp = WebResponseError::<DefaultError>::error_response(&err, &req);
+
+        assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        match resp.body().as_ref() {
+            Some(Body::Bytes(body)) => assert_eq!(
+                body,
+                r#"{"code":"file_too_large","message":"uploaded file is too large"}"#
+            ),
+            _ => unreachable!("expected a JSON body"),
+        }
+    }
+
+    #[test]
+    fn test_error_response_hides_internal_detail() {
+        let req = TestRequest::default().to_http_request();
+        let err = MultipartError::StorageError("<Error><Code>AccessDenied</Code></Error>".into());
+        let resp = WebResponseError::<DefaultError>::error_response(&err, &req);
+
+        assert_eq!(resp.status(), StatusCode::BAD_GATEWAY);
+        match resp.body().as_ref() {
+            Some(Body::Bytes(body)) => assert_eq!(
+                body,
+                r#"{"code":"storage_error","message":"failed to store upload"}"#
+            ),
+            _ => unreachable!("expected a JSON body"),
+        }
+    }
+}
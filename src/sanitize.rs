@@ -0,0 +1,150 @@
+use uuid::Uuid;
+
+const MAX_NAME_LEN: usize = 255;
+const MAX_EXTENSION_LEN: usize = 16;
+const FALLBACK_NAME: &str = "file";
+
+const WINDOWS_RESERVED: &[&str] = &[
+    "con", "prn", "aux", "nul", "com1", "com2", "com3", "com4", "com5", "com6", "com7", "com8",
+    "com9", "lpt1", "lpt2", "lpt3", "lpt4", "lpt5", "lpt6", "lpt7", "lpt8", "lpt9",
+];
+
+/// Turns a client supplied filename into one that is safe to use as a single path
+/// component on any platform: directories are dropped, reserved characters replaced,
+/// leading dots and Windows device names neutralised and the length capped at 255 bytes
+pub fn sanitize_filename(name: &str) -> String {
+    let base = name.rsplit(['/', '\\']).next().unwrap_or_default();
+
+    let cleaned: String = base
+        .chars()
+        .map(|c| match c {
+            '<' | '>' | ':' | '"' | '|' | '?' | '*' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+
+    let mut cleaned = cleaned
+        .trim_start_matches(['.', ' '])
+        .trim_end_matches(['.', ' '])
+        .to_string();
+
+    if cleaned.is_empty() {
+        return FALLBACK_NAME.to_string();
+    }
+
+    let stem = cleaned.split('.').next().unwrap_or_default();
+    if WINDOWS_RESERVED.contains(&stem.trim_end().to_ascii_lowercase().as_str()) {
+        cleaned.insert(0, '_');
+    }
+
+    truncate_preserving_extension(cleaned)
+}
+
+/// Generates a random, collision resistant storage name that keeps the extension, as long
+/// as it is made of ASCII letters and digits (dot separated for compound extensions like
+/// `tar.gz`) and at most 16 bytes long; any other extension is dropped
+pub fn random_storage_name(extension: Option<&str>) -> String {
+    with_extension(Uuid::new_v4().simple().to_string(), extension)
+}
+
+pub(crate) fn with_extension(stem: String, extension: Option<&str>) -> String {
+    match extension.filter(|ext| is_safe_extension(ext)) {
+        Some(ext) => format!("{}.{}", stem, ext),
+        None => stem,
+    }
+}
+
+fn is_safe_extension(extension: &str) -> bool {
+    extension.len() <= MAX_EXTENSION_LEN
+        && extension
+            .split('.')
+            .all(|part| !part.is_empty() && part.bytes().all(|b| b.is_ascii_alphanumeric()))
+}
+
+fn truncate_preserving_extension(name: String) -> String {
+    if name.len() <= MAX_NAME_LEN {
+        return name;
+    }
+
+    let ext = name
+        .rsplit_once('.')
+        .map(|(_, ext)| ext)
+        .filter(|ext| ext.len() < 16)
+        .map(|ext| format!(".{}", ext))
+        .unwrap_or_default();
+
+    let mut stem_len = MAX_NAME_LEN - ext.len();
+    while !name.is_char_boundary(stem_len) {
+        stem_len -= 1;
+    }
+
+    format!("{}{}", &name[..stem_len], ext)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_strips_path_traversal() {
+        assert_eq!(sanitize_filename("../../etc/passwd"), "passwd");
+        assert_eq!(sanitize_filename("..\\..\\boot.ini"), "boot.ini");
+        assert_eq!(sanitize_filename(".."), FALLBACK_NAME);
+        assert_eq!(sanitize_filename(""), FALLBACK_NAME);
+    }
+
+    #[test]
+    fn test_replaces_reserved_characters() {
+        assert_eq!(sanitize_filename("what?*.txt"), "what__.txt");
+        assert_eq!(sanitize_filename("tab\there.txt"), "tab_here.txt");
+        assert_eq!(sanitize_filename(".bashrc"), "bashrc");
+        assert_eq!(sanitize_filename("trailing. "), "trailing");
+    }
+
+    #[test]
+    fn test_windows_reserved_names() {
+        assert_eq!(sanitize_filename("CON"), "_CON");
+        assert_eq!(sanitize_filename("nul.txt"), "_nul.txt");
+        assert_eq!(sanitize_filename("console.txt"), "console.txt");
+    }
+
+    #[test]
+    fn test_truncates_long_names() {
+        let name = format!("{}.png", "é".repeat(200));
+        let sanitized = sanitize_filename(&name);
+        assert!(sanitized.len() <= MAX_NAME_LEN);
+        assert!(sanitized.ends_with(".png"));
+    }
+
+    #[test]
+    fn test_random_storage_name() {
+        let name = random_storage_name(Some("png"));
+        assert_eq!(name.len(), 36);
+        assert!(name.ends_with(".png"));
+        assert_ne!(name, random_storage_name(Some("png")));
+    }
+
+    #[test]
+    fn test_storage_name_rejects_unsafe_extensions() {
+        assert!(random_storage_name(Some("tar.gz")).ends_with(".tar.gz"));
+
+        for extension in [
+            "../../x",
+            "..",
+            "a/b",
+            "a\\b",
+            "png\0",
+            ".png",
+            "png.",
+            "",
+            "abcdefghijklmnopq",
+        ] {
+            let name = random_storage_name(Some(extension));
+            assert_eq!(name.len(), 32, "{:?} produced {:?}", extension, name);
+            assert!(name.bytes().all(|b| b.is_ascii_hexdigit()));
+        }
+
+        assert_eq!(with_extension("abc".to_string(), Some("../../x")), "abc");
+    }
+}
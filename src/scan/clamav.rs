@@ -0,0 +1,279 @@
+use std::io;
+#[cfg(unix)]
+use std::path::PathBuf;
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use crate::file::FileInfo;
+use crate::result::{MultipartError, MultipartResult};
+use crate::scan::{ScanVerdict, Scanner};
+
+/// Largest chunk sent per INSTREAM frame by default, well below clamd's `StreamMaxLength`
+const DEFAULT_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Time allowed for connecting, streaming the content and receiving the verdict
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Where a clamd daemon listens
+#[derive(Debug, Clone)]
+pub enum ClamAvAddress {
+    /// A `host:port` address
+    Tcp(String),
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+/// Scans uploads with a clamd compatible daemon using its `INSTREAM` command
+#[derive(Debug, Clone)]
+pub struct ClamAvScanner {
+    address: ClamAvAddress,
+    chunk_size: usize,
+    timeout: Duration,
+}
+
+impl ClamAvScanner {
+    pub fn new(address: ClamAvAddress) -> Self {
+        Self {
+            address,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+
+    pub fn tcp(address: &str) -> Self {
+        Self::new(ClamAvAddress::Tcp(address.to_string()))
+    }
+
+    #[cfg(unix)]
+    pub fn unix<P: Into<PathBuf>>(path: P) -> Self {
+        Self::new(ClamAvAddress::Unix(path.into()))
+    }
+
+    /// Maximum number of bytes sent per `INSTREAM` frame
+    pub fn chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = chunk_size.max(1);
+        self
+    }
+
+    /// Time allowed for the whole exchange with clamd, from connecting to the
+    /// verdict, one minute by default
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    async fn exchange<R>(&self, content: R) -> MultipartResult<ScanVerdict>
+    where
+        R: AsyncRead + Unpin,
+    {
+        match &self.address {
+            ClamAvAddress::Tcp(address) => {
+                let stream = TcpStream::connect(address)
+                    .await
+                    .map_err(|err| unreachable_daemon(address, err))?;
+                self.instream(stream, content).await
+            }
+            #[cfg(unix)]
+            ClamAvAddress::Unix(path) => {
+                let stream = tokio::net::UnixStream::connect(path)
+                    .await
+                    .map_err(|err| unreachable_daemon(&path.display(), err))?;
+                self.instream(stream, content).await
+            }
+        }
+    }
+
+    async fn instream<S, R>(&self, mut stream: S, mut content: R) -> MultipartResult<ScanVerdict>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+        R: AsyncRead + Unpin,
+    {
+        stream
+            .write_all(b"zINSTREAM\0")
+            .await
+            .map_err(daemon_error)?;
+
+        let mut frame = vec![0; self.chunk_size];
+        loop {
+            let read = content.read(&mut frame).await?;
+            if read == 0 {
+                break;
+            }
+
+            stream
+                .write_all(&(read as u32).to_be_bytes())
+                .await
+                .map_err(daemon_error)?;
+            stream
+                .write_all(&frame[..read])
+                .await
+                .map_err(daemon_error)?;
+        }
+
+        stream
+            .write_all(&0u32.to_be_bytes())
+            .await
+            .map_err(daemon_error)?;
+        stream.flush().await.map_err(daemon_error)?;
+
+        // `z` prefixed commands get a null terminated reply
+        let mut reply = vec![];
+        let mut buf = [0; 256];
+        while !reply.contains(&0) {
+            let read = stream.read(&mut buf).await.map_err(daemon_error)?;
+            if read == 0 {
+                break;
+            }
+            reply.extend_from_slice(&buf[..read]);
+        }
+
+        parse_reply(&reply)
+    }
+}
+
+impl Scanner for ClamAvScanner {
+    async fn scan<R: AsyncRead + Unpin>(
+        &self,
+        _file: &FileInfo,
+        content: R,
+    ) -> MultipartResult<ScanVerdict> {
+        tokio::time::timeout(self.timeout, self.exchange(content))
+            .await
+            .map_err(|_| {
+                MultipartError::ScanError(format!("clamd did not answer within {:?}", self.timeout))
+            })?
+    }
+}
+
+/// Failing to reach clamd is a gateway error, unlike failing to read the upload
+fn unreachable_daemon(address: &dyn std::fmt::Display, err: io::Error) -> MultipartError {
+    MultipartError::ScanError(format!("cannot connect to clamd at {}: {}", address, err))
+}
+
+fn daemon_error(err: io::Error) -> MultipartError {
+    MultipartError::ScanError(format!("clamd connection failed: {}", err))
+}
+
+/// Parses replies such as `stream: OK` or `stream: Eicar-Signature FOUND`
+fn parse_reply(reply: &[u8]) -> MultipartResult<ScanVerdict> {
+    let reply = String::from_utf8_lossy(reply);
+    let reply = reply.trim_end_matches(['\0', '\n']);
+    let status = reply.strip_prefix("stream: ").unwrap_or(reply);
+
+    if status == "OK" {
+        return Ok(ScanVerdict::Clean);
+    }
+
+    match status.strip_suffix(" FOUND") {
+        Some(signature) => Ok(ScanVerdict::Infected(signature.to_string())),
+        None => Err(MultipartError::ScanError(reply.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ntex::util::Bytes;
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::scan::ChunkReader;
+
+    /// Speaks just enough of the clamd protocol to flag content containing `EICAR`
+    async fn stand_in() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut command = [0; 10];
+                stream.read_exact(&mut command).await.unwrap();
+                assert_eq!(&command, b"zINSTREAM\0");
+
+                let mut content = vec![];
+                loop {
+                    let len = stream.read_u32().await.unwrap() as usize;
+                    if len == 0 {
+                        break;
+                    }
+
+                    assert!(len <= 4);
+                    let mut frame = vec![0; len];
+                    stream.read_exact(&mut frame).await.unwrap();
+                    content.extend(frame);
+                }
+
+                let infected = content.windows(5).any(|w| w == b"EICAR");
+                let reply: &[u8] = match infected {
+                    true => b"stream: Eicar-Test-Signature FOUND\0",
+                    false => b"stream: OK\0",
+                };
+                stream.write_all(reply).await.unwrap();
+            }
+        });
+
+        address
+    }
+
+    #[tokio::test]
+    async fn test_scan_against_stand_in() {
+        let scanner = ClamAvScanner::tcp(&stand_in().await).chunk_size(4);
+        let file = FileInfo::default();
+
+        let clean = [Bytes::from_static(b"hello "), Bytes::from_static(b"world")];
+        assert_eq!(
+            scanner.scan(&file, ChunkReader::new(&clean)).await.unwrap(),
+            ScanVerdict::Clean
+        );
+
+        let infected = [
+            Bytes::from_static(b"X5O!EIC"),
+            Bytes::from_static(b"AR-TEST"),
+        ];
+        assert_eq!(
+            scanner
+                .scan(&file, ChunkReader::new(&infected))
+                .await
+                .unwrap(),
+            ScanVerdict::Infected("Eicar-Test-Signature".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unreachable_or_silent_daemon_is_scan_error() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let file = FileInfo::default();
+
+        // accepts and reads, but never answers
+        let silent = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let _ = stream.read_to_end(&mut vec![]).await;
+        });
+        let scanner = ClamAvScanner::tcp(&address).timeout(Duration::from_millis(50));
+        assert!(matches!(
+            scanner.scan(&file, &b"hello"[..]).await,
+            Err(MultipartError::ScanError(_))
+        ));
+        silent.abort();
+        let _ = silent.await;
+
+        // nothing listens once the stand-in is gone
+        assert!(matches!(
+            ClamAvScanner::tcp(&address)
+                .scan(&file, &b"hello"[..])
+                .await,
+            Err(MultipartError::ScanError(_))
+        ));
+    }
+
+    #[test]
+    fn test_parse_reply() {
+        assert_eq!(parse_reply(b"stream: OK\0").unwrap(), ScanVerdict::Clean);
+        assert!(matches!(
+            parse_reply(b"INSTREAM size limit exceeded. ERROR\0"),
+            Err(MultipartError::ScanError(_))
+        ));
+    }
+}
@@ -0,0 +1,69 @@
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use ntex::util::Bytes;
+use tokio::io::{AsyncRead, ReadBuf};
+
+use crate::file::FileInfo;
+use crate::result::MultipartResult;
+
+mod clamav;
+
+pub use clamav::{ClamAvAddress, ClamAvScanner};
+
+/// Outcome of scanning an upload for malware
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScanVerdict {
+    Clean,
+    /// The engine matched the named signature
+    Infected(String),
+}
+
+/// An engine uploads can be checked with for malware, such as ClamAV
+pub trait Scanner {
+    /// Scans the complete content of `file`, read from `content` as the engine
+    /// consumes it; failing to reach the engine is an error rather than a verdict
+    fn scan<R: AsyncRead + Unpin>(
+        &self,
+        file: &FileInfo,
+        content: R,
+    ) -> impl Future<Output = MultipartResult<ScanVerdict>>;
+}
+
+/// Reads buffered chunks in order, so captures in memory scan like files on disk
+pub(crate) struct ChunkReader<'a> {
+    chunks: &'a [Bytes],
+    offset: usize,
+}
+
+impl<'a> ChunkReader<'a> {
+    pub(crate) fn new(chunks: &'a [Bytes]) -> Self {
+        Self { chunks, offset: 0 }
+    }
+}
+
+impl AsyncRead for ChunkReader<'_> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        while let Some((chunk, rest)) = this.chunks.split_first() {
+            if this.offset == chunk.len() {
+                this.chunks = rest;
+                this.offset = 0;
+                continue;
+            }
+
+            let len = (chunk.len() - this.offset).min(buf.remaining());
+            buf.put_slice(&chunk[this.offset..this.offset + len]);
+            this.offset += len;
+            break;
+        }
+
+        Poll::Ready(Ok(()))
+    }
+}
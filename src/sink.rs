@@ -0,0 +1,35 @@
+use ntex::util::Bytes;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+use crate::result::MultipartResult;
+
+/// Destination for the chunks of a captured field
+pub(crate) trait ChunkSink {
+    async fn write_chunk(&mut self, chunk: Bytes) -> MultipartResult<()>;
+
+    async fn finish(&mut self) -> MultipartResult<()> {
+        Ok(())
+    }
+}
+
+impl ChunkSink for Vec<Bytes> {
+    async fn write_chunk(&mut self, chunk: Bytes) -> MultipartResult<()> {
+        self.push(chunk);
+        Ok(())
+    }
+}
+
+/// Streams chunks straight into an [`AsyncWrite`] instead of buffering them
+pub(crate) struct WriteSink<'w, W>(pub &'w mut W);
+
+impl<W: AsyncWrite + Unpin> ChunkSink for WriteSink<'_, W> {
+    async fn write_chunk(&mut self, chunk: Bytes) -> MultipartResult<()> {
+        self.0.write_all(&chunk).await?;
+        Ok(())
+    }
+
+    async fn finish(&mut self) -> MultipartResult<()> {
+        self.0.flush().await?;
+        Ok(())
+    }
+}
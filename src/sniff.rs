@@ -0,0 +1,314 @@
+use crate::file::FileInfo;
+use crate::mime::essence;
+use crate::result::MultipartError::ValidationError;
+use crate::result::MultipartResult;
+use crate::result::MultipartValidationError::ContentTypeMismatch;
+
+/// Number of leading bytes inspected when sniffing content, enough to reach the
+/// PE header offset of Windows executables
+pub(crate) const SNIFF_LEN: usize = 64;
+
+/// Controls whether captured content is inspected for its real type
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum SniffMode {
+    #[default]
+    Disabled,
+    /// Records the detected type on [`FileInfo::detected_content_type`]
+    Detect,
+    /// Like `Detect`, but rejects parts whose declared type does not match their content
+    Enforce,
+}
+
+const SIGNATURES: &[(&[u8], &str)] = &[
+    (b"\x89PNG\r\n\x1a\n", "image/png"),
+    (b"\xff\xd8\xff", "image/jpeg"),
+    (b"GIF87a", "image/gif"),
+    (b"GIF89a", "image/gif"),
+    (b"II*\x00", "image/tiff"),
+    (b"MM\x00*", "image/tiff"),
+    (b"\x00\x00\x01\x00", "image/x-icon"),
+    (b"%PDF-", "application/pdf"),
+    (b"PK\x03\x04", "application/zip"),
+    (b"PK\x05\x06", "application/zip"),
+    (b"\x1f\x8b", "application/gzip"),
+    (b"7z\xbc\xaf\x27\x1c", "application/x-7z-compressed"),
+    (b"Rar!\x1a\x07", "application/vnd.rar"),
+    (b"\xff\xfb", "audio/mpeg"),
+    (b"\xff\xf3", "audio/mpeg"),
+    (b"\xff\xf2", "audio/mpeg"),
+    (b"OggS", "audio/ogg"),
+    (b"fLaC", "audio/flac"),
+    (b"\x1a\x45\xdf\xa3", "video/webm"),
+    (b"\x7fELF", "application/x-executable"),
+    (b"\xfe\xed\xfa\xce", "application/x-mach-binary"),
+    (b"\xfe\xed\xfa\xcf", "application/x-mach-binary"),
+    (b"\xce\xfa\xed\xfe", "application/x-mach-binary"),
+    (b"\xcf\xfa\xed\xfe", "application/x-mach-binary"),
+    (b"\x00asm", "application/wasm"),
+];
+
+/// Checks whether the leading bytes carry a format's header
+type HeaderCheck = fn(&[u8]) -> bool;
+
+/// Formats whose magic bytes are short enough to begin ordinary text, recognised
+/// by the header fields that follow them
+const HEADERS: &[(HeaderCheck, &str)] = &[
+    (is_bmp, "image/bmp"),
+    (is_id3, "audio/mpeg"),
+    (is_pe, "application/x-msdownload"),
+];
+
+/// Declared types accepted for each detected type, besides the detected type itself
+const COMPATIBLE: &[(&str, &[&str])] = &[
+    ("image/jpeg", &["image/jpg", "image/pjpeg"]),
+    ("image/png", &["image/x-png"]),
+    ("image/bmp", &["image/x-bmp", "image/x-ms-bmp"]),
+    ("image/x-icon", &["image/vnd.microsoft.icon"]),
+    ("audio/wav", &["audio/x-wav", "audio/wave"]),
+    ("audio/mpeg", &["audio/mp3"]),
+    ("audio/ogg", &["video/ogg", "application/ogg"]),
+    ("video/webm", &["audio/webm", "video/x-matroska"]),
+    ("application/gzip", &["application/x-gzip"]),
+    (
+        "application/x-msdownload",
+        &["application/vnd.microsoft.portable-executable"],
+    ),
+    (
+        "application/zip",
+        &[
+            "application/x-zip-compressed",
+            "application/java-archive",
+            "application/epub+zip",
+            "application/vnd.android.package-archive",
+            "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+            "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+            "application/vnd.openxmlformats-officedocument.presentationml.presentation",
+            "application/vnd.oasis.opendocument.text",
+            "application/vnd.oasis.opendocument.spreadsheet",
+            "application/vnd.oasis.opendocument.presentation",
+        ],
+    ),
+    (
+        "video/mp4",
+        &["audio/mp4", "video/x-m4v", "audio/x-m4a", "video/3gpp"],
+    ),
+];
+
+/// Detects the content type of `bytes` from its leading magic bytes
+pub fn sniff_mime(bytes: &[u8]) -> Option<&'static str> {
+    if bytes.len() >= 12 && &bytes[..4] == b"RIFF" {
+        return match &bytes[8..12] {
+            b"WEBP" => Some("image/webp"),
+            b"WAVE" => Some("audio/wav"),
+            b"AVI " => Some("video/x-msvideo"),
+            _ => None,
+        };
+    }
+
+    if bytes.len() >= 12 && &bytes[4..8] == b"ftyp" {
+        return match &bytes[8..12] {
+            b"qt  " => Some("video/quicktime"),
+            b"M4A " => Some("audio/mp4"),
+            b"avif" | b"avis" => Some("image/avif"),
+            b"heic" | b"heix" | b"mif1" | b"msf1" => Some("image/heic"),
+            _ => Some("video/mp4"),
+        };
+    }
+
+    let signature = SIGNATURES
+        .iter()
+        .find(|(magic, _)| bytes.starts_with(magic))
+        .map(|(_, mime)| *mime);
+
+    signature.or_else(|| {
+        HEADERS
+            .iter()
+            .find(|(matches, _)| matches(bytes))
+            .map(|(_, mime)| *mime)
+    })
+}
+
+/// `BM`, the file size, four reserved zero bytes, the pixel data offset and a
+/// known DIB header size
+fn is_bmp(bytes: &[u8]) -> bool {
+    let dib_header = bytes
+        .get(14..18)
+        .map(|size| u32::from_le_bytes([size[0], size[1], size[2], size[3]]));
+
+    bytes.starts_with(b"BM")
+        && bytes.get(6..10) == Some(&[0; 4])
+        && matches!(dib_header, Some(12 | 40 | 52 | 56 | 64 | 108 | 124))
+}
+
+/// `ID3`, a major version from 2 to 4, a revision, flags without the reserved low
+/// bits and a size of four 7 bit bytes
+fn is_id3(bytes: &[u8]) -> bool {
+    match bytes.get(..10) {
+        Some([b'I', b'D', b'3', 2..=4, revision, flags, size @ ..]) => {
+            *revision != 0xff && flags & 0x0f == 0 && size.iter().all(|b| b & 0x80 == 0)
+        }
+        _ => false,
+    }
+}
+
+/// `MZ` with the offset of the PE header at 0x3c pointing past the DOS header,
+/// which text can't produce as each of its bytes is at least 0x09
+fn is_pe(bytes: &[u8]) -> bool {
+    let offset = bytes
+        .get(0x3c..0x40)
+        .map(|offset| u32::from_le_bytes([offset[0], offset[1], offset[2], offset[3]]));
+
+    bytes.starts_with(b"MZ") && offset.is_some_and(|offset| (0x40..0x10000).contains(&offset))
+}
+
+/// Whether the declared content type is consistent with the sniffed one
+pub(crate) fn is_compatible(declared: &str, detected: Option<&str>) -> bool {
+    let declared = essence(declared);
+
+    match detected {
+        Some(detected) => {
+            declared == detected
+                || COMPATIBLE
+                    .iter()
+                    .any(|(mime, aliases)| *mime == detected && aliases.contains(&&*declared))
+        }
+        // content we cannot recognise only matches types we have no signature for
+        None => !is_detectable(&declared),
+    }
+}
+
+fn is_detectable(mime: &str) -> bool {
+    let riff_or_ftyp = [
+        "image/webp",
+        "audio/wav",
+        "video/x-msvideo",
+        "video/quicktime",
+        "video/mp4",
+        "image/avif",
+        "image/heic",
+    ];
+
+    riff_or_ftyp.contains(&mime)
+        || SIGNATURES.iter().any(|(_, m)| *m == mime)
+        || HEADERS.iter().any(|(_, m)| *m == mime)
+        || COMPATIBLE
+            .iter()
+            .any(|(m, aliases)| *m == mime || aliases.contains(&mime))
+}
+
+/// Collects the leading bytes of a field until there are enough to sniff
+#[derive(Default)]
+pub(crate) struct Sniffer {
+    head: Vec<u8>,
+    done: bool,
+}
+
+impl Sniffer {
+    /// Buffers the start of `chunk`, returns true once the head is ready to be sniffed
+    pub(crate) fn feed(&mut self, chunk: &[u8]) -> bool {
+        if self.done {
+            return false;
+        }
+
+        let wanted = SNIFF_LEN - self.head.len();
+        self.head
+            .extend_from_slice(&chunk[..wanted.min(chunk.len())]);
+        self.head.len() >= SNIFF_LEN
+    }
+
+    pub(crate) fn is_done(&self) -> bool {
+        self.done
+    }
+
+    /// Sniffs the buffered head, records it on `info` and enforces `mode`
+    pub(crate) fn apply(&mut self, info: &mut FileInfo, mode: SniffMode) -> MultipartResult<()> {
+        self.done = true;
+        let detected = sniff_mime(&self.head);
+        info.detected_content_type = detected.map(|m| m.to_string());
+
+        if mode == SniffMode::Enforce && !is_compatible(&info.content_type, detected) {
+            return Err(ValidationError(ContentTypeMismatch));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sniff_mime() {
+        assert_eq!(sniff_mime(b"\x89PNG\r\n\x1a\n...."), Some("image/png"));
+        assert_eq!(sniff_mime(b"\xff\xd8\xff\xe0"), Some("image/jpeg"));
+        assert_eq!(sniff_mime(b"GIF89a"), Some("image/gif"));
+        assert_eq!(
+            sniff_mime(b"RIFF\x00\x00\x00\x00WEBPVP8 "),
+            Some("image/webp")
+        );
+        assert_eq!(sniff_mime(b"%PDF-1.7"), Some("application/pdf"));
+        assert_eq!(sniff_mime(b"PK\x03\x04"), Some("application/zip"));
+        assert_eq!(sniff_mime(b"\x00\x00\x00\x18ftypmp42"), Some("video/mp4"));
+        assert_eq!(sniff_mime(b"hello world"), None);
+
+        let mut exe = b"MZ\x90\x00".to_vec();
+        exe.resize(0x3c, 0);
+        exe.extend_from_slice(&0x80u32.to_le_bytes());
+        assert_eq!(sniff_mime(&exe), Some("application/x-msdownload"));
+
+        let mut bmp = b"BM\x36\x00\x0c\x00\x00\x00\x00\x00\x36\x00\x00\x00".to_vec();
+        bmp.extend_from_slice(&40u32.to_le_bytes());
+        assert_eq!(sniff_mime(&bmp), Some("image/bmp"));
+
+        let mp3 = b"ID3\x04\x00\x00\x00\x00\x1f\x76TIT2";
+        assert_eq!(sniff_mime(mp3), Some("audio/mpeg"));
+    }
+
+    #[test]
+    fn test_text_is_not_mistaken_for_binary() {
+        let texts: [&[u8]; 3] = [
+            b"BMW,Munich,1916\nAudi,Ingolstadt,1909\nPorsche,Stuttgart,1931\n",
+            b"MZ-2023 quarterly report, see the appendix for the full figures ok",
+            b"ID3 tags store the artist and title of an mp3 file at its start",
+        ];
+
+        for text in texts {
+            assert_eq!(sniff_mime(text), None);
+            assert!(is_compatible("text/plain", sniff_mime(text)));
+        }
+    }
+
+    #[test]
+    fn test_is_compatible() {
+        assert!(is_compatible("image/png", Some("image/png")));
+        assert!(is_compatible("image/jpg", Some("image/jpeg")));
+        assert!(is_compatible(
+            "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+            Some("application/zip")
+        ));
+        assert!(is_compatible("text/plain; charset=utf-8", None));
+        assert!(!is_compatible(
+            "image/png",
+            Some("application/x-msdownload")
+        ));
+        assert!(!is_compatible("image/png", None));
+    }
+
+    #[test]
+    fn test_sniffer_across_chunks() {
+        let mut info = FileInfo {
+            content_type: "image/png".to_string(),
+            ..FileInfo::default()
+        };
+
+        let mut sniffer = Sniffer::default();
+        assert!(!sniffer.feed(b"\x89PN"));
+        let rest = [&b"G\r\n\x1a\n"[..], &[b'0'; 59]].concat();
+        assert!(sniffer.feed(&rest));
+        sniffer.apply(&mut info, SniffMode::Enforce).unwrap();
+
+        assert!(sniffer.is_done());
+        assert_eq!(info.detected_content_type.as_deref(), Some("image/png"));
+    }
+}
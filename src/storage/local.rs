@@ -0,0 +1,221 @@
+use std::io::{Error, ErrorKind};
+use std::path::{Component, Path, PathBuf};
+
+use ntex::util::Bytes;
+use tokio::fs::{self, File, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use uuid::Uuid;
+
+use crate::file::FileInfo;
+use crate::result::MultipartResult;
+use crate::storage::{StorageBackend, StorageWriter};
+
+/// Stores objects as files below a root directory, keys being relative paths
+#[derive(Debug, Clone)]
+pub struct LocalStorage {
+    root: PathBuf,
+}
+
+impl LocalStorage {
+    pub fn new<P: AsRef<Path>>(root: P) -> Self {
+        Self {
+            root: root.as_ref().to_path_buf(),
+        }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Resolves `key` below the root, rejecting absolute keys and `..` components
+    pub fn path(&self, key: &str) -> MultipartResult<PathBuf> {
+        let relative = Path::new(key);
+        let is_safe = !key.is_empty()
+            && relative
+                .components()
+                .all(|c| matches!(c, Component::Normal(_)));
+
+        if !is_safe {
+            let msg = format!("invalid storage key: {}", key);
+            return Err(Error::new(ErrorKind::InvalidInput, msg).into());
+        }
+
+        Ok(self.root.join(relative))
+    }
+
+    async fn create(&self, key: &str) -> MultipartResult<LocalWriter> {
+        let path = self.path(key)?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+
+        // every writer gets its own partial file, so concurrent writes to a key don't collide
+        let partial = partial_path(&path);
+        let file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&partial)
+            .await?;
+
+        Ok(LocalWriter {
+            path,
+            partial,
+            file: Some(file),
+        })
+    }
+}
+
+impl StorageBackend for LocalStorage {
+    type Writer = LocalWriter;
+
+    async fn put(&self, key: &str, file: &FileInfo, chunks: &[Bytes]) -> MultipartResult<()> {
+        let mut writer = self.stream(key, file).await?;
+        for chunk in chunks {
+            writer.write(chunk.clone()).await?;
+        }
+
+        writer.finish().await
+    }
+
+    async fn stream(&self, key: &str, _file: &FileInfo) -> MultipartResult<LocalWriter> {
+        self.create(key).await
+    }
+
+    async fn delete(&self, key: &str) -> MultipartResult<()> {
+        match fs::remove_file(self.path(key)?).await {
+            Err(err) if err.kind() != ErrorKind::NotFound => Err(err.into()),
+            _ => Ok(()),
+        }
+    }
+
+    async fn exists(&self, key: &str) -> MultipartResult<bool> {
+        Ok(fs::try_exists(self.path(key)?).await?)
+    }
+}
+
+/// Writes to a uniquely named `<path>.<uuid>.partial` and renames it into place when
+/// finished; the partial file is removed if the writer fails or is dropped unfinished
+pub struct LocalWriter {
+    path: PathBuf,
+    partial: PathBuf,
+    file: Option<File>,
+}
+
+impl StorageWriter for LocalWriter {
+    async fn write(&mut self, chunk: Bytes) -> MultipartResult<()> {
+        match self.file.as_mut() {
+            Some(file) => Ok(file.write_all(&chunk).await?),
+            None => Err(Error::new(ErrorKind::BrokenPipe, "writer is closed").into()),
+        }
+    }
+
+    async fn finish(&mut self) -> MultipartResult<()> {
+        if let Some(mut file) = self.file.take() {
+            let result = match file.flush().await {
+                Ok(()) => fs::rename(&self.partial, &self.path).await,
+                Err(err) => Err(err),
+            };
+
+            if let Err(err) = result {
+                let _ = fs::remove_file(&self.partial).await;
+                return Err(err.into());
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn abort(&mut self) -> MultipartResult<()> {
+        if self.file.take().is_some() {
+            fs::remove_file(&self.partial).await?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for LocalWriter {
+    fn drop(&mut self) {
+        if self.file.take().is_some() {
+            let _ = std::fs::remove_file(&self.partial);
+        }
+    }
+}
+
+fn partial_path(path: &Path) -> PathBuf {
+    let mut partial = path.to_path_buf().into_os_string();
+    partial.push(format!(".{}.partial", Uuid::new_v4().simple()));
+    PathBuf::from(partial)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn storage(name: &str) -> LocalStorage {
+        let root = std::env::temp_dir().join(format!("medullah-local-{}", name));
+        let _ = std::fs::remove_dir_all(&root);
+        LocalStorage::new(root)
+    }
+
+    #[tokio::test]
+    async fn test_put_exists_delete() {
+        let storage = storage("put");
+        let chunks = [Bytes::from_static(b"hello "), Bytes::from_static(b"world")];
+
+        storage
+            .put("docs/a.txt", &FileInfo::default(), &chunks)
+            .await
+            .unwrap();
+        assert!(storage.exists("docs/a.txt").await.unwrap());
+        assert_eq!(
+            std::fs::read(storage.root().join("docs/a.txt")).unwrap(),
+            b"hello world"
+        );
+
+        storage.delete("docs/a.txt").await.unwrap();
+        assert!(!storage.exists("docs/a.txt").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_abort_leaves_nothing_behind() {
+        let storage = storage("abort");
+        let mut writer = storage.stream("a.txt", &FileInfo::default()).await.unwrap();
+        writer.write(Bytes::from_static(b"partial")).await.unwrap();
+        writer.abort().await.unwrap();
+
+        assert!(!storage.exists("a.txt").await.unwrap());
+        assert_eq!(std::fs::read_dir(storage.root()).unwrap().count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_writers_to_one_key() {
+        let storage = storage("concurrent");
+        let file = FileInfo::default();
+
+        let mut first = storage.stream("a.txt", &file).await.unwrap();
+        let mut second = storage.stream("a.txt", &file).await.unwrap();
+        first.write(Bytes::from_static(b"same")).await.unwrap();
+        second.write(Bytes::from_static(b"same")).await.unwrap();
+
+        first.finish().await.unwrap();
+        second.finish().await.unwrap();
+
+        let dropped = storage.stream("b.txt", &file).await.unwrap();
+        drop(dropped);
+
+        assert_eq!(
+            std::fs::read(storage.root().join("a.txt")).unwrap(),
+            b"same"
+        );
+        assert_eq!(std::fs::read_dir(storage.root()).unwrap().count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_rejects_traversal() {
+        let storage = storage("traversal");
+        assert!(storage.path("../etc/passwd").is_err());
+        assert!(storage.path("/etc/passwd").is_err());
+        assert!(storage.path("ok/file.txt").is_ok());
+    }
+}
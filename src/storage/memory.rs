@@ -0,0 +1,84 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use ntex::util::Bytes;
+
+use crate::file::FileInfo;
+use crate::result::MultipartResult;
+use crate::storage::{StorageBackend, StorageWriter};
+
+type Objects = Arc<Mutex<HashMap<String, Vec<u8>>>>;
+
+/// Keeps objects in memory, mainly useful in tests; clones share the same objects
+#[derive(Debug, Clone, Default)]
+pub struct MemoryStorage {
+    objects: Objects,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, key: &str) -> Option<Vec<u8>> {
+        self.objects.lock().unwrap().get(key).cloned()
+    }
+
+    pub fn keys(&self) -> Vec<String> {
+        self.objects.lock().unwrap().keys().cloned().collect()
+    }
+}
+
+impl StorageBackend for MemoryStorage {
+    type Writer = MemoryWriter;
+
+    async fn put(&self, key: &str, _file: &FileInfo, chunks: &[Bytes]) -> MultipartResult<()> {
+        let object = chunks.concat();
+        self.objects.lock().unwrap().insert(key.to_string(), object);
+        Ok(())
+    }
+
+    async fn stream(&self, key: &str, _file: &FileInfo) -> MultipartResult<MemoryWriter> {
+        Ok(MemoryWriter {
+            key: key.to_string(),
+            buffer: vec![],
+            objects: self.objects.clone(),
+        })
+    }
+
+    async fn delete(&self, key: &str) -> MultipartResult<()> {
+        self.objects.lock().unwrap().remove(key);
+        Ok(())
+    }
+
+    async fn exists(&self, key: &str) -> MultipartResult<bool> {
+        Ok(self.objects.lock().unwrap().contains_key(key))
+    }
+}
+
+pub struct MemoryWriter {
+    key: String,
+    buffer: Vec<u8>,
+    objects: Objects,
+}
+
+impl StorageWriter for MemoryWriter {
+    async fn write(&mut self, chunk: Bytes) -> MultipartResult<()> {
+        self.buffer.extend_from_slice(&chunk);
+        Ok(())
+    }
+
+    async fn finish(&mut self) -> MultipartResult<()> {
+        let object = std::mem::take(&mut self.buffer);
+        self.objects
+            .lock()
+            .unwrap()
+            .insert(self.key.clone(), object);
+        Ok(())
+    }
+
+    async fn abort(&mut self) -> MultipartResult<()> {
+        self.buffer.clear();
+        Ok(())
+    }
+}
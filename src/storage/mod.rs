@@ -0,0 +1,70 @@
+use std::future::Future;
+
+use ntex::util::Bytes;
+
+use crate::file::FileInfo;
+use crate::result::MultipartResult;
+use crate::sink::ChunkSink;
+
+mod local;
+mod memory;
+mod s3;
+mod sigv4;
+
+pub use local::{LocalStorage, LocalWriter};
+pub use memory::{MemoryStorage, MemoryWriter};
+pub use s3::{S3Config, S3Storage, S3Writer, S3_MIN_PART_SIZE};
+
+/// A place captured uploads can be written to, such as a local directory or an object store
+pub trait StorageBackend {
+    type Writer: StorageWriter;
+
+    /// Stores a complete object under `key`, replacing any existing one
+    fn put(
+        &self,
+        key: &str,
+        file: &FileInfo,
+        chunks: &[Bytes],
+    ) -> impl Future<Output = MultipartResult<()>>;
+
+    /// Opens a writer that receives the object under `key` chunk by chunk
+    fn stream(
+        &self,
+        key: &str,
+        file: &FileInfo,
+    ) -> impl Future<Output = MultipartResult<Self::Writer>>;
+
+    fn delete(&self, key: &str) -> impl Future<Output = MultipartResult<()>>;
+
+    fn exists(&self, key: &str) -> impl Future<Output = MultipartResult<bool>>;
+}
+
+/// An object being streamed into a [`StorageBackend`]; it only becomes visible
+/// under its key once [`StorageWriter::finish`] succeeds
+pub trait StorageWriter {
+    fn write(&mut self, chunk: Bytes) -> impl Future<Output = MultipartResult<()>>;
+
+    fn finish(&mut self) -> impl Future<Output = MultipartResult<()>>;
+
+    /// Discards everything written so far
+    fn abort(&mut self) -> impl Future<Output = MultipartResult<()>>;
+}
+
+/// A file streamed into a [`StorageBackend`]
+#[derive(Debug, Clone)]
+pub struct StoredFile {
+    pub key: String,
+    pub file: FileInfo,
+}
+
+pub(crate) struct StorageSink<'w, W>(pub &'w mut W);
+
+impl<W: StorageWriter> ChunkSink for StorageSink<'_, W> {
+    async fn write_chunk(&mut self, chunk: Bytes) -> MultipartResult<()> {
+        self.0.write(chunk).await
+    }
+
+    async fn finish(&mut self) -> MultipartResult<()> {
+        self.0.finish().await
+    }
+}
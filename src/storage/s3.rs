@@ -0,0 +1,619 @@
+use std::rc::Rc;
+use std::time::SystemTime;
+
+use ntex::http::client::{Client, ClientResponse};
+use ntex::http::{Method, StatusCode};
+use ntex::util::{Bytes, BytesMut};
+
+use crate::file::FileInfo;
+use crate::result::{MultipartError, MultipartResult};
+use crate::storage::sigv4::{self, Credentials, SignableRequest};
+use crate::storage::{StorageBackend, StorageWriter};
+
+/// Smallest part size S3 accepts for every part but the last one (5 MiB)
+pub const S3_MIN_PART_SIZE: usize = 5 * 1024 * 1024;
+
+/// Connection settings of an S3-compatible object store (AWS S3, MinIO, R2, ...)
+///
+/// HTTPS endpoints require ntex to be built with its `openssl` or `rustls` feature.
+#[derive(Debug, Clone)]
+pub struct S3Config {
+    endpoint: String,
+    region: String,
+    bucket: String,
+    access_key: String,
+    secret_key: String,
+    key_prefix: String,
+    part_size: usize,
+    path_style: bool,
+}
+
+impl S3Config {
+    pub fn new(
+        endpoint: &str,
+        region: &str,
+        bucket: &str,
+        access_key: &str,
+        secret_key: &str,
+    ) -> Self {
+        Self {
+            endpoint: endpoint.trim_end_matches('/').to_string(),
+            region: region.to_string(),
+            bucket: bucket.to_string(),
+            access_key: access_key.to_string(),
+            secret_key: secret_key.to_string(),
+            key_prefix: String::new(),
+            part_size: S3_MIN_PART_SIZE,
+            path_style: true,
+        }
+    }
+
+    /// Prepended to every key, e.g. `uploads/`
+    pub fn key_prefix(mut self, prefix: &str) -> Self {
+        self.key_prefix = prefix.to_string();
+        self
+    }
+
+    /// Size of each part of a multipart upload, objects smaller than this are sent
+    /// with a single `PutObject`; S3 itself rejects parts below [`S3_MIN_PART_SIZE`]
+    pub fn part_size(mut self, part_size: usize) -> Self {
+        self.part_size = part_size.max(1);
+        self
+    }
+
+    /// Addresses the bucket as `<bucket>.<endpoint host>` instead of `<endpoint>/<bucket>`
+    pub fn virtual_hosted(mut self) -> Self {
+        self.path_style = false;
+        self
+    }
+}
+
+/// Stores objects in an S3-compatible bucket, streaming large uploads part by part
+#[derive(Clone)]
+pub struct S3Storage {
+    inner: Rc<S3Inner>,
+}
+
+struct S3Inner {
+    config: S3Config,
+    client: Client,
+}
+
+impl S3Storage {
+    pub fn new(config: S3Config) -> Self {
+        Self {
+            inner: Rc::new(S3Inner {
+                config,
+                client: Client::new(),
+            }),
+        }
+    }
+
+    pub fn config(&self) -> &S3Config {
+        &self.inner.config
+    }
+
+    /// Object metadata derived from the uploaded file
+    fn metadata(file: &FileInfo) -> Vec<(String, String)> {
+        let mut headers = vec![];
+        if !file.content_type.is_empty() {
+            headers.push(("content-type".to_string(), file.content_type.clone()));
+        }
+
+        if !file.name.is_empty() {
+            let name = sigv4::uri_encode(&file.sanitized_name(), true);
+            headers.push(("x-amz-meta-filename".to_string(), name));
+        }
+
+        if !file.field.is_empty() {
+            let field = sigv4::uri_encode(&file.field, true);
+            headers.push(("x-amz-meta-field".to_string(), field));
+        }
+
+        headers
+    }
+
+    /// Sends a signed request, failing on any non-success status
+    async fn send(
+        &self,
+        method: Method,
+        key: &str,
+        query: &[(&str, &str)],
+        extra_headers: &[(String, String)],
+        body: Bytes,
+    ) -> MultipartResult<ClientResponse> {
+        let mut response = self
+            .send_raw(method.clone(), key, query, extra_headers, body)
+            .await?;
+
+        if response.status().is_success() {
+            return Ok(response);
+        }
+
+        let status = response.status();
+        let message = response.body().await.unwrap_or_default();
+        Err(MultipartError::StorageError(format!(
+            "{} {} failed with {}: {}",
+            method,
+            key,
+            status,
+            String::from_utf8_lossy(&message)
+        )))
+    }
+
+    async fn send_raw(
+        &self,
+        method: Method,
+        key: &str,
+        query: &[(&str, &str)],
+        extra_headers: &[(String, String)],
+        body: Bytes,
+    ) -> MultipartResult<ClientResponse> {
+        let config = &self.inner.config;
+        let (scheme, endpoint_host) = config
+            .endpoint
+            .split_once("://")
+            .unwrap_or(("https", &config.endpoint));
+
+        let object = sigv4::uri_encode(&format!("{}{}", config.key_prefix, key), false);
+        let (host, path) = match config.path_style {
+            true => (
+                endpoint_host.to_string(),
+                format!("/{}/{}", sigv4::uri_encode(&config.bucket, true), object),
+            ),
+            false => (
+                format!("{}.{}", config.bucket, endpoint_host),
+                format!("/{}", object),
+            ),
+        };
+
+        let payload_hash = sigv4::sha256_hex(&body);
+        let datetime = sigv4::amz_datetime(SystemTime::now());
+
+        let mut headers = vec![
+            ("host".to_string(), host.clone()),
+            ("x-amz-content-sha256".to_string(), payload_hash.clone()),
+            ("x-amz-date".to_string(), datetime.clone()),
+        ];
+        headers.extend_from_slice(extra_headers);
+
+        let credentials = Credentials {
+            access_key: &config.access_key,
+            secret_key: &config.secret_key,
+            region: &config.region,
+        };
+
+        let authorization = sigv4::authorization(
+            &credentials,
+            &SignableRequest {
+                method: method.as_str(),
+                path: &path,
+                query,
+                headers: &headers,
+                payload_hash: &payload_hash,
+            },
+            &datetime,
+        );
+
+        let mut url = format!("{}://{}{}", scheme, host, path);
+        if !query.is_empty() {
+            url = format!("{}?{}", url, sigv4::canonical_query(query));
+        }
+
+        let mut request = self
+            .inner
+            .client
+            .request(method, url)
+            .set_header("authorization", authorization);
+
+        for (name, value) in headers {
+            request = request.set_header(name, value);
+        }
+
+        request
+            .send_body(body)
+            .await
+            .map_err(|err| MultipartError::StorageError(err.to_string()))
+    }
+
+    async fn body(response: &mut ClientResponse) -> MultipartResult<Bytes> {
+        response
+            .body()
+            .await
+            .map_err(|err| MultipartError::StorageError(err.to_string()))
+    }
+}
+
+impl StorageBackend for S3Storage {
+    type Writer = S3Writer;
+
+    async fn put(&self, key: &str, file: &FileInfo, chunks: &[Bytes]) -> MultipartResult<()> {
+        let body = Bytes::from(chunks.concat());
+        self.send(Method::PUT, key, &[], &Self::metadata(file), body)
+            .await?;
+        Ok(())
+    }
+
+    async fn stream(&self, key: &str, file: &FileInfo) -> MultipartResult<S3Writer> {
+        Ok(S3Writer {
+            storage: self.clone(),
+            key: key.to_string(),
+            metadata: Self::metadata(file),
+            buffer: BytesMut::new(),
+            upload_id: None,
+            etags: vec![],
+        })
+    }
+
+    async fn delete(&self, key: &str) -> MultipartResult<()> {
+        self.send(Method::DELETE, key, &[], &[], Bytes::new())
+            .await?;
+        Ok(())
+    }
+
+    async fn exists(&self, key: &str) -> MultipartResult<bool> {
+        let response = self
+            .send_raw(Method::HEAD, key, &[], &[], Bytes::new())
+            .await?;
+
+        match response.status() {
+            status if status.is_success() => Ok(true),
+            StatusCode::NOT_FOUND => Ok(false),
+            status => Err(MultipartError::StorageError(format!(
+                "HEAD {} failed with {}",
+                key, status
+            ))),
+        }
+    }
+}
+
+/// Buffers chunks into parts of [`S3Config::part_size`] and uploads each one as soon
+/// as it is full; objects that never fill a part are sent with a single `PutObject`
+///
+/// A writer dropped with a multipart upload still open, without
+/// [`finish`](StorageWriter::finish) or [`abort`](StorageWriter::abort) having completed,
+/// aborts it from a task spawned on the current runtime. That task is best effort (it is
+/// lost if the runtime shuts down or the request fails), so a bucket lifecycle rule
+/// that expires incomplete multipart uploads is still recommended.
+pub struct S3Writer {
+    storage: S3Storage,
+    key: String,
+    metadata: Vec<(String, String)>,
+    buffer: BytesMut,
+    upload_id: Option<String>,
+    etags: Vec<String>,
+}
+
+impl S3Writer {
+    async fn upload_part(&mut self, part: Bytes) -> MultipartResult<()> {
+        let upload_id = match &self.upload_id {
+            Some(upload_id) => upload_id.clone(),
+            None => self.initiate().await?,
+        };
+
+        let part_number = (self.etags.len() + 1).to_string();
+        let query = [
+            ("partNumber", part_number.as_str()),
+            ("uploadId", upload_id.as_str()),
+        ];
+
+        let response = self
+            .storage
+            .send(Method::PUT, &self.key, &query, &[], part)
+            .await?;
+
+        let etag = response
+            .headers()
+            .get("etag")
+            .and_then(|v| v.to_str().ok())
+            .ok_or_else(|| MultipartError::StorageError("part upload returned no ETag".into()))?;
+
+        self.etags.push(etag.to_string());
+        Ok(())
+    }
+
+    async fn initiate(&mut self) -> MultipartResult<String> {
+        let query = [("uploads", "")];
+        let mut response = self
+            .storage
+            .send(
+                Method::POST,
+                &self.key,
+                &query,
+                &self.metadata,
+                Bytes::new(),
+            )
+            .await?;
+
+        let body = S3Storage::body(&mut response).await?;
+        let upload_id = xml_value(&String::from_utf8_lossy(&body), "UploadId")
+            .ok_or_else(|| MultipartError::StorageError("missing UploadId".to_string()))?;
+
+        self.upload_id = Some(upload_id.clone());
+        Ok(upload_id)
+    }
+}
+
+impl StorageWriter for S3Writer {
+    async fn write(&mut self, chunk: Bytes) -> MultipartResult<()> {
+        self.buffer.extend_from_slice(&chunk);
+
+        let part_size = self.storage.config().part_size;
+        while self.buffer.len() >= part_size {
+            let part = self.buffer.split_to(part_size).freeze();
+            self.upload_part(part).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn finish(&mut self) -> MultipartResult<()> {
+        let rest = self.buffer.split().freeze();
+
+        let upload_id = match self.upload_id.clone() {
+            Some(upload_id) => upload_id,
+            None => {
+                self.storage
+                    .send(Method::PUT, &self.key, &[], &self.metadata, rest)
+                    .await?;
+                return Ok(());
+            }
+        };
+
+        if !rest.is_empty() {
+            self.upload_part(rest).await?;
+        }
+
+        let parts: String = self
+            .etags
+            .iter()
+            .enumerate()
+            .map(|(i, etag)| {
+                format!(
+                    "<Part><PartNumber>{}</PartNumber><ETag>{}</ETag></Part>",
+                    i + 1,
+                    etag
+                )
+            })
+            .collect();
+
+        let body = format!(
+            "<CompleteMultipartUpload>{}</CompleteMultipartUpload>",
+            parts
+        );
+
+        let query = [("uploadId", upload_id.as_str())];
+        self.storage
+            .send(Method::POST, &self.key, &query, &[], Bytes::from(body))
+            .await?;
+
+        self.upload_id = None;
+        Ok(())
+    }
+
+    async fn abort(&mut self) -> MultipartResult<()> {
+        self.buffer.clear();
+
+        if let Some(upload_id) = self.upload_id.take() {
+            let query = [("uploadId", upload_id.as_str())];
+            self.storage
+                .send(Method::DELETE, &self.key, &query, &[], Bytes::new())
+                .await?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for S3Writer {
+    fn drop(&mut self) {
+        if let Some(upload_id) = self.upload_id.take() {
+            let storage = self.storage.clone();
+            let key = std::mem::take(&mut self.key);
+            ntex::rt::spawn(async move {
+                let query = [("uploadId", upload_id.as_str())];
+                let _ = storage
+                    .send(Method::DELETE, &key, &query, &[], Bytes::new())
+                    .await;
+            });
+        }
+    }
+}
+
+fn xml_value(xml: &str, tag: &str) -> Option<String> {
+    let open = format!("<{}>", tag);
+    let close = format!("</{}>", tag);
+    let start = xml.find(&open)? + open.len();
+    let end = xml[start..].find(&close)? + start;
+    Some(xml[start..end].to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+
+    use ntex::web::{self, test, App, HttpRequest, HttpResponse};
+
+    use super::*;
+
+    /// In-process stand-in for the subset of the S3 API used by [`S3Storage`]
+    #[derive(Default)]
+    struct StandIn {
+        objects: HashMap<String, (Vec<u8>, Option<String>)>,
+        uploads: HashMap<String, (Vec<Vec<u8>>, Option<String>)>,
+        part_uploads: usize,
+    }
+
+    type Store = Arc<Mutex<StandIn>>;
+
+    async fn stand_in(
+        req: HttpRequest,
+        body: Bytes,
+        store: web::types::State<Store>,
+    ) -> HttpResponse {
+        let authorized = req
+            .headers()
+            .get("authorization")
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.starts_with("AWS4-HMAC-SHA256 Credential=key/"));
+        if !authorized {
+            return HttpResponse::Forbidden().finish();
+        }
+
+        let query: HashMap<&str, &str> = req
+            .query_string()
+            .split('&')
+            .filter(|p| !p.is_empty())
+            .map(|p| p.split_once('=').unwrap_or((p, "")))
+            .collect();
+
+        let filename = req
+            .headers()
+            .get("x-amz-meta-filename")
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.to_string());
+
+        let path = req.path().to_string();
+        let mut store = store.lock().unwrap();
+
+        match (req.method().as_str(), query.get("uploadId")) {
+            ("POST", None) if query.contains_key("uploads") => {
+                let id = format!("upload-{}", store.uploads.len() + 1);
+                store.uploads.insert(id.clone(), (vec![], filename));
+                HttpResponse::Ok().body(format!(
+                    "<InitiateMultipartUploadResult><UploadId>{}</UploadId></InitiateMultipartUploadResult>",
+                    id
+                ))
+            }
+            ("PUT", Some(id)) => {
+                store.part_uploads += 1;
+                let parts = &mut store.uploads.get_mut(*id).unwrap().0;
+                parts.push(body.to_vec());
+                HttpResponse::Ok()
+                    .header("etag", format!("\"etag-{}\"", parts.len()))
+                    .finish()
+            }
+            ("POST", Some(id)) => {
+                let (parts, filename) = store.uploads.remove(*id).unwrap();
+                store.objects.insert(path, (parts.concat(), filename));
+                HttpResponse::Ok().body("<CompleteMultipartUploadResult/>")
+            }
+            ("DELETE", Some(id)) => {
+                store.uploads.remove(*id);
+                HttpResponse::NoContent().finish()
+            }
+            ("PUT", None) => {
+                store.objects.insert(path, (body.to_vec(), filename));
+                HttpResponse::Ok().finish()
+            }
+            ("HEAD", None) => match store.objects.contains_key(&path) {
+                true => HttpResponse::Ok().finish(),
+                false => HttpResponse::NotFound().finish(),
+            },
+            ("DELETE", None) => {
+                store.objects.remove(&path);
+                HttpResponse::NoContent().finish()
+            }
+            _ => HttpResponse::new(StatusCode::NOT_IMPLEMENTED),
+        }
+    }
+
+    fn server(store: Store) -> test::TestServer {
+        test::server(move || {
+            App::new()
+                .state(store.clone())
+                .default_service(web::to(stand_in))
+        })
+    }
+
+    fn storage(srv: &test::TestServer, part_size: usize) -> S3Storage {
+        let endpoint = format!("http://{}", srv.addr());
+        let config = S3Config::new(&endpoint, "us-east-1", "media", "key", "secret")
+            .key_prefix("uploads/")
+            .part_size(part_size);
+
+        S3Storage::new(config)
+    }
+
+    fn file_info() -> FileInfo {
+        FileInfo {
+            name: "clip.mp4".to_string(),
+            field: "video".to_string(),
+            content_type: "video/mp4".to_string(),
+            ..FileInfo::default()
+        }
+    }
+
+    #[ntex::test]
+    async fn test_multipart_upload_part_by_part() {
+        let store = Store::default();
+        let srv = server(store.clone());
+        let storage = storage(&srv, 8);
+
+        let mut writer = storage.stream("clip.mp4", &file_info()).await.unwrap();
+        for chunk in ["0123", "4567890", "12345678", "9"] {
+            writer.write(Bytes::from(chunk)).await.unwrap();
+        }
+        writer.finish().await.unwrap();
+
+        let store = store.lock().unwrap();
+        let (object, filename) = &store.objects["/media/uploads/clip.mp4"];
+        assert_eq!(object, b"01234567890123456789");
+        assert_eq!(filename.as_deref(), Some("clip.mp4"));
+        assert_eq!(store.part_uploads, 3);
+        assert!(store.uploads.is_empty());
+    }
+
+    #[ntex::test]
+    async fn test_small_object_put_exists_delete() {
+        let store = Store::default();
+        let srv = server(store.clone());
+        let storage = storage(&srv, S3_MIN_PART_SIZE);
+
+        let chunks = [Bytes::from_static(b"tiny")];
+        storage.put("a.txt", &file_info(), &chunks).await.unwrap();
+        assert!(storage.exists("a.txt").await.unwrap());
+
+        storage.delete("a.txt").await.unwrap();
+        assert!(!storage.exists("a.txt").await.unwrap());
+    }
+
+    #[ntex::test]
+    async fn test_abort_multipart_upload() {
+        let store = Store::default();
+        let srv = server(store.clone());
+        let storage = storage(&srv, 4);
+
+        let mut writer = storage.stream("clip.mp4", &file_info()).await.unwrap();
+        writer.write(Bytes::from("0123456789")).await.unwrap();
+        writer.abort().await.unwrap();
+
+        let store = store.lock().unwrap();
+        assert!(store.objects.is_empty());
+        assert!(store.uploads.is_empty());
+    }
+
+    #[ntex::test]
+    async fn test_dropped_writer_aborts_multipart_upload() {
+        let store = Store::default();
+        let srv = server(store.clone());
+        let storage = storage(&srv, 4);
+
+        let mut writer = storage.stream("clip.mp4", &file_info()).await.unwrap();
+        writer.write(Bytes::from("0123456789")).await.unwrap();
+        assert_eq!(store.lock().unwrap().uploads.len(), 1);
+        drop(writer);
+
+        for _ in 0..100 {
+            if store.lock().unwrap().uploads.is_empty() {
+                break;
+            }
+            ntex::time::sleep(ntex::time::Millis(10)).await;
+        }
+
+        let store = store.lock().unwrap();
+        assert!(store.objects.is_empty());
+        assert!(store.uploads.is_empty());
+    }
+}
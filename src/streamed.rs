@@ -0,0 +1,136 @@
+use std::path::{Path, PathBuf};
+
+use tokio::fs::{File, OpenOptions};
+use uuid::Uuid;
+
+use crate::file::FileInfo;
+use crate::result::{MultipartError, MultipartResult};
+use crate::scan::{ScanVerdict, Scanner};
+
+/// An upload that was streamed to a temporary file on disk rather than buffered in memory.
+///
+/// The temporary file is removed when this value is dropped, unless it was moved
+/// elsewhere with [`StreamedFile::persist`] or kept with [`StreamedFile::keep`].
+#[derive(Debug)]
+pub struct StreamedFile {
+    pub(crate) file: FileInfo,
+    path: PathBuf,
+    persisted: bool,
+}
+
+impl StreamedFile {
+    /// Creates a new temporary file with an unguessable name inside `dir`; an existing
+    /// file is never opened, so a name planted in a shared directory can't be hijacked
+    pub(crate) async fn create(dir: &Path) -> MultipartResult<(Self, File)> {
+        let name = format!("medullah-{}.upload", Uuid::new_v4().simple());
+        let path = dir.join(name);
+
+        let file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .await?;
+
+        let streamed = Self {
+            file: FileInfo::default(),
+            path,
+            persisted: false,
+        };
+        Ok((streamed, file))
+    }
+
+    pub fn file(&self) -> &FileInfo {
+        &self.file
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Checks the file on disk with `scanner`, reading it as the engine consumes it;
+    /// fails with [`MultipartError::Infected`] when malware is found
+    pub async fn scan<S: Scanner>(&self, scanner: &S) -> MultipartResult<()> {
+        let content = File::open(&self.path).await?;
+        match scanner.scan(&self.file, content).await? {
+            ScanVerdict::Clean => Ok(()),
+            ScanVerdict::Infected(signature) => Err(MultipartError::Infected { signature }),
+        }
+    }
+
+    /// Moves the temporary file to `path`, falling back to copying when a rename
+    /// is not possible (e.g. across filesystems)
+    pub async fn persist<P: AsRef<Path>>(mut self, path: &P) -> MultipartResult<()> {
+        if tokio::fs::rename(&self.path, path).await.is_err() {
+            tokio::fs::copy(&self.path, path).await?;
+            let _ = tokio::fs::remove_file(&self.path).await;
+        }
+
+        self.persisted = true;
+        Ok(())
+    }
+
+    /// Keeps the temporary file on disk and returns its path
+    pub fn keep(mut self) -> PathBuf {
+        self.persisted = true;
+        self.path.clone()
+    }
+}
+
+impl Drop for StreamedFile {
+    fn drop(&mut self) {
+        if !self.persisted {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+
+    /// Flags content containing `EICAR`, reading it the way a real engine would
+    struct Signature;
+
+    impl Scanner for Signature {
+        async fn scan<R: AsyncRead + Unpin>(
+            &self,
+            _file: &FileInfo,
+            mut content: R,
+        ) -> MultipartResult<ScanVerdict> {
+            let mut bytes = vec![];
+            content.read_to_end(&mut bytes).await?;
+            Ok(match bytes.windows(5).any(|w| w == b"EICAR") {
+                true => ScanVerdict::Infected("Eicar".to_string()),
+                false => ScanVerdict::Clean,
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_create_unique_and_removed_on_drop() {
+        let dir = std::env::temp_dir();
+        let (first, _) = StreamedFile::create(&dir).await.unwrap();
+        let (second, _) = StreamedFile::create(&dir).await.unwrap();
+
+        assert_ne!(first.path(), second.path());
+        assert!(first.path().exists());
+
+        let path = first.path().to_path_buf();
+        drop(first);
+        assert!(!path.exists());
+    }
+
+    #[tokio::test]
+    async fn test_scan_reads_file_on_disk() {
+        let (streamed, mut file) = StreamedFile::create(&std::env::temp_dir()).await.unwrap();
+        file.write_all(b"X5O!EICAR-TEST").await.unwrap();
+        file.flush().await.unwrap();
+
+        assert!(matches!(
+            streamed.scan(&Signature).await,
+            Err(MultipartError::Infected { signature }) if signature == "Eicar"
+        ));
+    }
+}
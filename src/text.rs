@@ -0,0 +1,97 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use crate::result::MultipartError::ValidationError;
+use crate::result::MultipartResult;
+use crate::result::MultipartValidationError::InvalidTextField;
+
+/// Default size limit applied to text fields that have no explicit limit (64 KiB)
+pub const DEFAULT_TEXT_FIELD_LIMIT: usize = 64 * 1024;
+
+/// Plain (non-file) form fields collected while reading the multipart stream
+#[derive(Debug, Default, Clone)]
+pub struct TextFields {
+    values: HashMap<String, Vec<String>>,
+}
+
+impl TextFields {
+    pub(crate) fn into_values(self) -> HashMap<String, Vec<String>> {
+        self.values
+    }
+
+    pub(crate) fn insert(&mut self, field: String, value: String) {
+        self.values.entry(field).or_default().push(value);
+    }
+
+    /// Returns the first value sent for the field
+    pub fn get(&self, field: &str) -> Option<&str> {
+        self.values
+            .get(field)
+            .and_then(|values| values.first())
+            .map(|v| v.as_str())
+    }
+
+    /// Returns every value sent for the field, in the order they were received
+    pub fn get_all(&self, field: &str) -> &[String] {
+        self.values.get(field).map(|v| v.as_slice()).unwrap_or(&[])
+    }
+
+    /// Parses the first value sent for the field into `T`
+    pub fn parse<T: FromStr>(&self, field: &str) -> MultipartResult<Option<T>> {
+        match self.get(field) {
+            None => Ok(None),
+            Some(value) => value
+                .parse()
+                .map(Some)
+                .map_err(|_| ValidationError(InvalidTextField)),
+        }
+    }
+
+    pub fn contains(&self, field: &str) -> bool {
+        self.values.contains_key(field)
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &[String])> {
+        self.values
+            .iter()
+            .map(|(field, values)| (field.as_str(), values.as_slice()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::result::MultipartError;
+
+    #[test]
+    fn test_get_and_parse() {
+        let mut fields = TextFields::default();
+        fields.insert("age".to_string(), "42".to_string());
+        fields.insert("tag".to_string(), "a".to_string());
+        fields.insert("tag".to_string(), "b".to_string());
+
+        assert_eq!(fields.get("age"), Some("42"));
+        assert_eq!(fields.parse::<u32>("age").unwrap(), Some(42));
+        assert_eq!(fields.parse::<u32>("missing").unwrap(), None);
+        assert_eq!(fields.get_all("tag"), ["a", "b"]);
+    }
+
+    #[test]
+    fn test_parse_invalid_value() {
+        let mut fields = TextFields::default();
+        fields.insert("age".to_string(), "old".to_string());
+
+        assert!(matches!(
+            fields.parse::<u32>("age"),
+            Err(MultipartError::ValidationError(InvalidTextField))
+        ));
+    }
+}
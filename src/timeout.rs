@@ -0,0 +1,96 @@
+use std::time::Duration;
+
+use futures::{Stream, StreamExt};
+use tokio::time::{timeout_at, Instant};
+
+use crate::result::{MultipartError, MultipartResult};
+
+/// Guards against clients that hold a request open by trickling data;
+/// `None` disables the respective check
+#[derive(Debug, Clone, Copy)]
+pub struct Timeouts {
+    /// Longest wait for the next chunk or part
+    pub idle: Option<Duration>,
+    /// Longest time reading the request may take, counted from the first read
+    pub deadline: Option<Duration>,
+    /// Minimum average number of body bytes per second across the request, also
+    /// enforced while waiting for data, so a stalled client fails without `idle`
+    pub min_rate: Option<usize>,
+    /// How long the request is read before `min_rate` is enforced, so slow starts
+    /// aren't mistaken for slow clients; defaults to five seconds
+    pub rate_grace_period: Duration,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self {
+            idle: None,
+            deadline: None,
+            min_rate: None,
+            rate_grace_period: Duration::from_secs(5),
+        }
+    }
+}
+
+/// Enforces [`Timeouts`] while an [`Uploader`](crate::Uploader) reads its request
+#[derive(Debug, Default)]
+pub(crate) struct Clock {
+    pub(crate) timeouts: Timeouts,
+    started: Option<Instant>,
+    /// Body bytes received so far, as last passed to [`Clock::check_rate`]
+    received: usize,
+}
+
+impl Clock {
+    /// Waits for the next item of `stream`, giving up once the idle timeout or
+    /// the deadline passes, or the average rate falls below the minimum
+    pub(crate) async fn next<S: Stream + Unpin>(
+        &mut self,
+        stream: &mut S,
+    ) -> MultipartResult<Option<S::Item>> {
+        let now = Instant::now();
+        let started = *self.started.get_or_insert(now);
+
+        let idle = self.timeouts.idle.map(|idle| now + idle);
+        let deadline = self.timeouts.deadline.map(|deadline| started + deadline);
+        // without more data, the average rate drops below the minimum at this point
+        let too_slow = self.timeouts.min_rate.filter(|rate| *rate > 0).map(|rate| {
+            let allowance = Duration::from_secs_f64(self.received as f64 / rate as f64);
+            started + allowance.max(self.timeouts.rate_grace_period)
+        });
+        let Some(until) = idle.into_iter().chain(deadline).chain(too_slow).min() else {
+            return Ok(stream.next().await);
+        };
+
+        match timeout_at(until, stream.next()).await {
+            Ok(item) => Ok(item),
+            Err(_) if Some(until) == deadline => Err(MultipartError::DeadlineExceeded),
+            Err(_) if Some(until) == too_slow => {
+                let elapsed = started.elapsed().as_secs_f64();
+                let rate = (self.received as f64 / elapsed) as usize;
+                Err(MultipartError::TooSlow { rate })
+            }
+            Err(_) => Err(MultipartError::IdleTimeout),
+        }
+    }
+
+    /// Checks that `received` body bytes since the first read meet the minimum rate
+    pub(crate) fn check_rate(&mut self, received: usize) -> MultipartResult<()> {
+        self.received = received;
+
+        let (Some(min_rate), Some(started)) = (self.timeouts.min_rate, self.started) else {
+            return Ok(());
+        };
+
+        let elapsed = started.elapsed();
+        if elapsed < self.timeouts.rate_grace_period || elapsed.is_zero() {
+            return Ok(());
+        }
+
+        let rate = (received as f64 / elapsed.as_secs_f64()) as usize;
+        match rate < min_rate {
+            true => Err(MultipartError::TooSlow { rate }),
+            false => Ok(()),
+        }
+    }
+}
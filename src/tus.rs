@@ -0,0 +1,745 @@
+use std::cell::RefCell;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::{Display, Formatter};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use futures::StreamExt;
+use md5::Md5;
+use ntex::http::header::{HeaderName, HeaderValue, CACHE_CONTROL, CONTENT_TYPE, LOCATION};
+use ntex::http::{Method, StatusCode};
+use ntex::web::types::{Payload, State};
+use ntex::web::{
+    self, DefaultError, HttpRequest, HttpResponse, HttpResponseBuilder, ServiceConfig,
+    WebResponseError,
+};
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use tokio::fs::{self, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use uuid::Uuid;
+
+use crate::file::FileInfo;
+use crate::mime::validate_mime;
+use crate::result::MultipartError::ValidationError;
+use crate::result::MultipartValidationError::{LowerSizeError, UpperSizeError};
+use crate::result::{MultipartError, MultipartResult};
+use crate::uploader::UploadData;
+
+const TUS_VERSION: &str = "1.0.0";
+const TUS_EXTENSIONS: &str = "creation,termination,checksum";
+const TUS_CHECKSUMS: &str = "sha1,md5,sha256";
+const OFFSET_CONTENT_TYPE: &str = "application/offset+octet-stream";
+
+/// Status the checksum extension answers with when a chunk does not match its digest
+const CHECKSUM_MISMATCH: u16 = 460;
+
+/// Data files with a PATCH or DELETE in progress
+type LockedUploads = Rc<RefCell<BTreeSet<PathBuf>>>;
+
+/// Exclusive access to an upload, so the offset a PATCH was checked against stays
+/// valid until its body is written and a failed chunk is cut off without touching
+/// anyone else's data; released on drop
+struct UploadLock {
+    locked: LockedUploads,
+    path: PathBuf,
+}
+
+impl Drop for UploadLock {
+    fn drop(&mut self) {
+        self.locked.borrow_mut().remove(&self.path);
+    }
+}
+
+/// A [`MultipartError`] rendered as usual, plus the `Tus-Resumable` header every
+/// tus response carries
+#[derive(Debug)]
+struct TusError(MultipartError);
+
+type TusResult = Result<HttpResponse, TusError>;
+
+impl From<MultipartError> for TusError {
+    fn from(err: MultipartError) -> Self {
+        Self(err)
+    }
+}
+
+impl From<std::io::Error> for TusError {
+    fn from(err: std::io::Error) -> Self {
+        Self(err.into())
+    }
+}
+
+impl Display for TusError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl WebResponseError<DefaultError> for TusError {
+    fn status_code(&self) -> StatusCode {
+        WebResponseError::<DefaultError>::status_code(&self.0)
+    }
+
+    fn error_response(&self, req: &HttpRequest) -> HttpResponse {
+        let mut response = WebResponseError::<DefaultError>::error_response(&self.0, req);
+        response.headers_mut().insert(
+            HeaderName::from_static("tus-resumable"),
+            HeaderValue::from_static(TUS_VERSION),
+        );
+        response
+    }
+}
+
+/// A tus 1.0 server storing resumable uploads below a directory.
+///
+/// Supports the creation, termination and checksum extensions; mount it on a scope:
+///
+/// ```ignore
+/// web::scope("/files").configure(TusServer::new("uploads", upload_data).configure())
+/// ```
+///
+/// The `filename` and `filetype` metadata describe the file, which is validated
+/// against the same size and MIME rules as [`UploadData`] when the upload is created.
+/// Only one PATCH or DELETE runs per upload at a time; others get `423 Locked`. The
+/// server and its clones share these locks, which do not reach across worker threads
+/// each building their own server.
+#[derive(Clone)]
+pub struct TusServer {
+    root: PathBuf,
+    upload: UploadData<'static>,
+    on_complete: Option<CompleteCallback>,
+    locked: LockedUploads,
+}
+
+type CompleteCallback = Rc<dyn Fn(&TusUpload)>;
+
+/// The state of a resumable upload
+#[derive(Debug, Clone)]
+pub struct TusUpload {
+    pub id: String,
+    pub length: usize,
+    pub offset: usize,
+    /// Built from the `filename` and `filetype` metadata, its `size` is the current offset
+    pub file: FileInfo,
+    pub metadata: HashMap<String, String>,
+}
+
+impl TusUpload {
+    pub fn is_complete(&self) -> bool {
+        self.offset == self.length
+    }
+}
+
+/// What is persisted next to the data of an upload
+#[derive(Serialize, Deserialize)]
+struct TusRecord {
+    length: usize,
+    metadata: HashMap<String, String>,
+}
+
+impl TusServer {
+    pub fn new<P: AsRef<Path>>(root: P, upload: UploadData<'static>) -> Self {
+        Self {
+            root: root.as_ref().to_path_buf(),
+            upload,
+            on_complete: None,
+            locked: LockedUploads::default(),
+        }
+    }
+
+    /// Called once, by the PATCH that delivers the last byte of an upload
+    pub fn on_complete<F: Fn(&TusUpload) + 'static>(mut self, callback: F) -> Self {
+        self.on_complete = Some(Rc::new(callback));
+        self
+    }
+
+    /// Registers the tus endpoints, to be passed to `Scope::configure` or `App::configure`
+    pub fn configure(self) -> impl FnOnce(&mut ServiceConfig) {
+        move |cfg| {
+            cfg.state(self)
+                .route("", web::method(Method::OPTIONS).to(options))
+                .route("", web::post().to(create))
+                .route("/{id}", web::head().to(status))
+                .route("/{id}", web::patch().to(append))
+                .route("/{id}", web::delete().to(terminate));
+        }
+    }
+
+    /// Path the data of the upload `id` is written to
+    pub fn path(&self, id: &str) -> PathBuf {
+        self.root.join(id)
+    }
+
+    /// Looks up an upload, `None` when it does not exist or was terminated
+    pub async fn upload(&self, id: &str) -> MultipartResult<Option<TusUpload>> {
+        if !is_upload_id(id) {
+            return Ok(None);
+        }
+
+        let record = match fs::read(self.record_path(id)).await {
+            Ok(record) => record,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+
+        let record: TusRecord = serde_json::from_slice(&record)
+            .map_err(|err| MultipartError::StorageError(err.to_string()))?;
+        let offset = fs::metadata(self.path(id)).await?.len() as usize;
+
+        let mut file = self.file_info(&record.metadata);
+        file.size = offset;
+
+        Ok(Some(TusUpload {
+            id: id.to_string(),
+            length: record.length,
+            offset,
+            file,
+            metadata: record.metadata,
+        }))
+    }
+
+    async fn create(
+        &self,
+        length: usize,
+        metadata: HashMap<String, String>,
+    ) -> MultipartResult<TusUpload> {
+        let ud = &self.upload;
+        if ud.upper_size.is_some_and(|upper| length > upper) {
+            return Err(ValidationError(UpperSizeError));
+        }
+
+        if length < ud.lower_size {
+            return Err(ValidationError(LowerSizeError));
+        }
+
+        let file = self.file_info(&metadata);
+        validate_mime(&file.content_type, &ud.allowed_mimes, &ud.denied_mimes)?;
+
+        let id = Uuid::new_v4().simple().to_string();
+        let record = TusRecord { length, metadata };
+        let json = serde_json::to_vec(&record)
+            .map_err(|err| MultipartError::StorageError(err.to_string()))?;
+
+        fs::create_dir_all(&self.root).await?;
+        fs::File::create(self.path(&id)).await?;
+        fs::write(self.record_path(&id), json).await?;
+
+        Ok(TusUpload {
+            id,
+            length,
+            offset: 0,
+            file,
+            metadata: record.metadata,
+        })
+    }
+
+    async fn terminate(&self, id: &str) -> MultipartResult<()> {
+        fs::remove_file(self.record_path(id)).await?;
+        match fs::remove_file(self.path(id)).await {
+            Err(err) if err.kind() != ErrorKind::NotFound => Err(err.into()),
+            _ => Ok(()),
+        }
+    }
+
+    fn file_info(&self, metadata: &HashMap<String, String>) -> FileInfo {
+        let name = metadata.get("filename").or_else(|| metadata.get("name"));
+        let content_type = metadata.get("filetype").or_else(|| metadata.get("type"));
+
+        FileInfo::new(
+            self.upload.field.to_string(),
+            name.cloned().unwrap_or_default(),
+            content_type
+                .cloned()
+                .unwrap_or_else(|| "application/octet-stream".to_string()),
+        )
+    }
+
+    fn record_path(&self, id: &str) -> PathBuf {
+        self.root.join(format!("{}.info", id))
+    }
+
+    /// `None` while another request holds the upload
+    fn lock(&self, id: &str) -> Option<UploadLock> {
+        let path = self.path(id);
+        let acquired = self.locked.borrow_mut().insert(path.clone());
+        acquired.then(|| UploadLock {
+            locked: self.locked.clone(),
+            path,
+        })
+    }
+}
+
+async fn options(tus: State<TusServer>) -> HttpResponse {
+    let mut response = tus_response(StatusCode::NO_CONTENT);
+    response
+        .header("tus-version", TUS_VERSION)
+        .header("tus-extension", TUS_EXTENSIONS)
+        .header("tus-checksum-algorithm", TUS_CHECKSUMS);
+
+    if let Some(upper_size) = tus.upload.upper_size {
+        response.header("tus-max-size", upper_size.to_string());
+    }
+
+    response.finish()
+}
+
+async fn create(req: HttpRequest, tus: State<TusServer>) -> TusResult {
+    if let Some(response) = version_mismatch(&req) {
+        return Ok(response);
+    }
+
+    let length = header(&req, "upload-length").and_then(|v| v.parse().ok());
+    let metadata = match header(&req, "upload-metadata") {
+        None => Some(HashMap::new()),
+        Some(metadata) => parse_metadata(metadata),
+    };
+
+    let (Some(length), Some(metadata)) = (length, metadata) else {
+        return Ok(tus_response(StatusCode::BAD_REQUEST).finish());
+    };
+
+    let upload = tus.create(length, metadata).await?;
+    let location = format!("{}/{}", req.path().trim_end_matches('/'), upload.id);
+    Ok(tus_response(StatusCode::CREATED)
+        .header(LOCATION, location)
+        .finish())
+}
+
+async fn status(req: HttpRequest, tus: State<TusServer>) -> TusResult {
+    if let Some(response) = version_mismatch(&req) {
+        return Ok(response);
+    }
+
+    let Some(upload) = tus.upload(upload_id(&req)).await? else {
+        return Ok(tus_response(StatusCode::NOT_FOUND).finish());
+    };
+
+    let mut response = tus_response(StatusCode::OK);
+    response
+        .header("upload-offset", upload.offset.to_string())
+        .header("upload-length", upload.length.to_string())
+        .header(CACHE_CONTROL, "no-store");
+
+    if !upload.metadata.is_empty() {
+        response.header("upload-metadata", encode_metadata(&upload.metadata));
+    }
+
+    Ok(response.finish())
+}
+
+async fn append(req: HttpRequest, tus: State<TusServer>, mut payload: Payload) -> TusResult {
+    if let Some(response) = version_mismatch(&req) {
+        return Ok(response);
+    }
+
+    let Some(_lock) = tus.lock(upload_id(&req)) else {
+        return Ok(tus_response(StatusCode::LOCKED).finish());
+    };
+
+    let Some(upload) = tus.upload(upload_id(&req)).await? else {
+        return Ok(tus_response(StatusCode::NOT_FOUND).finish());
+    };
+
+    if header(&req, CONTENT_TYPE.as_str()) != Some(OFFSET_CONTENT_TYPE) {
+        return Ok(tus_response(StatusCode::UNSUPPORTED_MEDIA_TYPE).finish());
+    }
+
+    let Some(offset) = header(&req, "upload-offset").and_then(|v| v.parse::<usize>().ok()) else {
+        return Ok(tus_response(StatusCode::BAD_REQUEST).finish());
+    };
+
+    let mut checksum = match header(&req, "upload-checksum").map(ChunkChecksum::parse) {
+        Some(None) => return Ok(tus_response(StatusCode::BAD_REQUEST).finish()),
+        Some(checksum) => checksum,
+        None => None,
+    };
+
+    if offset != upload.offset {
+        return Ok(tus_response(StatusCode::CONFLICT).finish());
+    }
+
+    let path = tus.path(&upload.id);
+    let mut file = OpenOptions::new().append(true).open(&path).await?;
+
+    let mut received = 0;
+    while let Some(chunk) = payload.next().await {
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(err) => {
+                file.flush().await?;
+                // without a digest to check against, what arrived is kept to resume from
+                if checksum.is_some() {
+                    file.set_len(offset as u64).await?;
+                }
+
+                return Err(MultipartError::Incomplete {
+                    received,
+                    source: ntex_multipart::MultipartError::Payload(err),
+                }
+                .into());
+            }
+        };
+
+        received += chunk.len();
+        if offset + received > upload.length {
+            file.set_len(offset as u64).await?;
+            return Err(ValidationError(UpperSizeError).into());
+        }
+
+        if let Some(checksum) = checksum.as_mut() {
+            checksum.update(&chunk);
+        }
+        file.write_all(&chunk).await?;
+    }
+
+    file.flush().await?;
+    if checksum.is_some_and(|checksum| !checksum.matches()) {
+        file.set_len(offset as u64).await?;
+        let status = StatusCode::from_u16(CHECKSUM_MISMATCH).unwrap();
+        return Ok(tus_response(status).finish());
+    }
+
+    let offset = offset + received;
+    // an empty PATCH to a finished upload completes nothing
+    if received > 0 && offset == upload.length {
+        if let Some(callback) = &tus.on_complete {
+            let mut upload = upload.clone();
+            upload.offset = offset;
+            upload.file.size = offset;
+            callback(&upload);
+        }
+    }
+
+    Ok(tus_response(StatusCode::NO_CONTENT)
+        .header("upload-offset", offset.to_string())
+        .finish())
+}
+
+async fn terminate(req: HttpRequest, tus: State<TusServer>) -> TusResult {
+    if let Some(response) = version_mismatch(&req) {
+        return Ok(response);
+    }
+
+    let id = upload_id(&req);
+    let Some(_lock) = tus.lock(id) else {
+        return Ok(tus_response(StatusCode::LOCKED).finish());
+    };
+
+    if tus.upload(id).await?.is_none() {
+        return Ok(tus_response(StatusCode::NOT_FOUND).finish());
+    }
+
+    tus.terminate(id).await?;
+    Ok(tus_response(StatusCode::NO_CONTENT).finish())
+}
+
+fn tus_response(status: StatusCode) -> HttpResponseBuilder {
+    let mut response = HttpResponse::build(status);
+    response.header("tus-resumable", TUS_VERSION);
+    response
+}
+
+fn version_mismatch(req: &HttpRequest) -> Option<HttpResponse> {
+    match header(req, "tus-resumable") {
+        Some(TUS_VERSION) => None,
+        _ => Some(
+            tus_response(StatusCode::PRECONDITION_FAILED)
+                .header("tus-version", TUS_VERSION)
+                .finish(),
+        ),
+    }
+}
+
+fn header<'r>(req: &'r HttpRequest, name: &str) -> Option<&'r str> {
+    req.headers().get(name).and_then(|v| v.to_str().ok())
+}
+
+fn upload_id(req: &HttpRequest) -> &str {
+    req.match_info().get("id").unwrap_or_default()
+}
+
+/// Ids are generated as simple UUIDs; anything else never names an upload
+fn is_upload_id(id: &str) -> bool {
+    id.len() == 32 && id.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Parses `Upload-Metadata`: comma separated pairs of a key and a base64 value,
+/// the value being optional
+fn parse_metadata(header: &str) -> Option<HashMap<String, String>> {
+    let mut metadata = HashMap::new();
+    for pair in header.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        let (key, value) = match pair.split_once(' ') {
+            Some((key, value)) => {
+                let value = STANDARD.decode(value.trim()).ok()?;
+                (key, String::from_utf8(value).ok()?)
+            }
+            None => (pair, String::new()),
+        };
+
+        metadata.insert(key.to_string(), value);
+    }
+
+    Some(metadata)
+}
+
+fn encode_metadata(metadata: &HashMap<String, String>) -> String {
+    metadata
+        .iter()
+        .map(|(key, value)| match value.is_empty() {
+            true => key.clone(),
+            false => format!("{} {}", key, STANDARD.encode(value)),
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// The digest a PATCH body is checked against, from `Upload-Checksum: <algorithm> <base64>`
+enum ChunkChecksum {
+    Sha1(Sha1, Vec<u8>),
+    Md5(Md5, Vec<u8>),
+    Sha256(Sha256, Vec<u8>),
+}
+
+impl ChunkChecksum {
+    fn parse(header: &str) -> Option<Self> {
+        let (algorithm, digest) = header.trim().split_once(' ')?;
+        let digest = STANDARD.decode(digest.trim()).ok()?;
+
+        match algorithm {
+            "sha1" => Some(ChunkChecksum::Sha1(Sha1::new(), digest)),
+            "md5" => Some(ChunkChecksum::Md5(Md5::new(), digest)),
+            "sha256" => Some(ChunkChecksum::Sha256(Sha256::new(), digest)),
+            _ => None,
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            ChunkChecksum::Sha1(hasher, _) => hasher.update(data),
+            ChunkChecksum::Md5(hasher, _) => hasher.update(data),
+            ChunkChecksum::Sha256(hasher, _) => hasher.update(data),
+        }
+    }
+
+    fn matches(self) -> bool {
+        match self {
+            ChunkChecksum::Sha1(hasher, digest) => hasher.finalize().as_slice() == digest,
+            ChunkChecksum::Md5(hasher, digest) => hasher.finalize().as_slice() == digest,
+            ChunkChecksum::Sha256(hasher, digest) => hasher.finalize().as_slice() == digest,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use futures::channel::mpsc;
+    use ntex::http::{self, error::PayloadError};
+    use ntex::util::Bytes;
+    use ntex::web::test::{call_service, init_service, TestRequest};
+    use ntex::web::App;
+
+    use super::*;
+
+    fn server(name: &str) -> TusServer {
+        let root = std::env::temp_dir().join(format!("medullah-tus-{}", name));
+        let _ = std::fs::remove_dir_all(&root);
+
+        TusServer::new(
+            root,
+            UploadData {
+                field: "video",
+                upper_size: Some(16),
+                allowed_mimes: vec!["video/*"],
+                ..UploadData::default()
+            },
+        )
+    }
+
+    fn request(method: Method, uri: &str) -> TestRequest {
+        TestRequest::default()
+            .method(method)
+            .uri(uri)
+            .header("tus-resumable", TUS_VERSION)
+    }
+
+    fn patch(uri: &str, offset: usize, body: &'static [u8]) -> TestRequest {
+        request(Method::PATCH, uri)
+            .header(CONTENT_TYPE, OFFSET_CONTENT_TYPE)
+            .header("upload-offset", offset.to_string())
+            .set_payload(body)
+    }
+
+    fn header(response: &web::WebResponse, name: &str) -> String {
+        let value = response.headers().get(name).unwrap();
+        value.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn test_parse_metadata() {
+        let metadata =
+            parse_metadata("filename Y2xpcC5tcDQ=,filetype dmlkZW8vbXA0,private").unwrap();
+        assert_eq!(metadata["filename"], "clip.mp4");
+        assert_eq!(metadata["filetype"], "video/mp4");
+        assert_eq!(metadata["private"], "");
+        assert!(parse_metadata("filename !!!").is_none());
+    }
+
+    #[ntex::test]
+    async fn test_resumable_upload() {
+        let completed = Rc::new(Cell::new(0));
+        let counter = completed.clone();
+        let tus = server("flow").on_complete(move |upload| {
+            assert_eq!(upload.file.name, "clip.mp4");
+            counter.set(upload.offset);
+        });
+
+        let app = init_service(
+            App::new().service(web::scope("/files").configure(tus.clone().configure())),
+        )
+        .await;
+
+        let created = request(Method::POST, "/files")
+            .header("upload-length", "10")
+            .header(
+                "upload-metadata",
+                "filename Y2xpcC5tcDQ=,filetype dmlkZW8vbXA0",
+            )
+            .to_request();
+        let response = call_service(&app, created).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let location = header(&response, "location");
+        assert!(location.starts_with("/files/"));
+
+        let response = call_service(&app, patch(&location, 0, b"hello").to_request()).await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert_eq!(header(&response, "upload-offset"), "5");
+
+        let stale = call_service(&app, patch(&location, 0, b"hello").to_request()).await;
+        assert_eq!(stale.status(), StatusCode::CONFLICT);
+
+        let head = call_service(&app, request(Method::HEAD, &location).to_request()).await;
+        assert_eq!(header(&head, "upload-offset"), "5");
+        assert_eq!(header(&head, "upload-length"), "10");
+
+        // sha1 of "world" is 7c211433f02071597741e6ff5a8ea34789abbf43
+        let corrupted = patch(&location, 5, b"w0rld")
+            .header("upload-checksum", "sha1 fCEUM/AgcVl3Qeb/Wo6jR4mrv0M=")
+            .to_request();
+        let response = call_service(&app, corrupted).await;
+        assert_eq!(response.status().as_u16(), CHECKSUM_MISMATCH);
+
+        let verified = patch(&location, 5, b"world")
+            .header("upload-checksum", "sha1 fCEUM/AgcVl3Qeb/Wo6jR4mrv0M=")
+            .to_request();
+        let response = call_service(&app, verified).await;
+        assert_eq!(header(&response, "upload-offset"), "10");
+        assert_eq!(completed.get(), 10);
+
+        // a retried or empty PATCH to the finished upload completes nothing again
+        completed.set(0);
+        let response = call_service(&app, patch(&location, 10, b"").to_request()).await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert_eq!(completed.get(), 0);
+
+        let id = location.rsplit('/').next().unwrap();
+        assert_eq!(std::fs::read(tus.path(id)).unwrap(), b"helloworld");
+
+        let deleted = call_service(&app, request(Method::DELETE, &location).to_request()).await;
+        assert_eq!(deleted.status(), StatusCode::NO_CONTENT);
+        let head = call_service(&app, request(Method::HEAD, &location).to_request()).await;
+        assert_eq!(head.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[ntex::test]
+    async fn test_concurrent_patches_are_serialized() {
+        let tus = server("concurrent");
+        let app = init_service(
+            App::new().service(web::scope("/files").configure(tus.clone().configure())),
+        )
+        .await;
+
+        let created = request(Method::POST, "/files")
+            .header("upload-length", "10")
+            .header("upload-metadata", "filetype dmlkZW8vbXA0")
+            .to_request();
+        let location = header(&call_service(&app, created).await, "location");
+        let path = tus.path(location.rsplit('/').next().unwrap());
+
+        // the first PATCH stalls halfway through its body
+        let (sender, body) = mpsc::unbounded::<Result<Bytes, PayloadError>>();
+        let mut slow = patch(&location, 0, b"").to_request();
+        slow.replace_payload(http::Payload::Stream(Box::pin(body)));
+        sender
+            .unbounded_send(Ok(Bytes::from_static(b"hel")))
+            .unwrap();
+        let mut slow = Box::pin(call_service(&app, slow));
+        for _ in 0..1000 {
+            if std::fs::metadata(&path).unwrap().len() == 3 {
+                break;
+            }
+            assert!(futures::poll!(slow.as_mut()).is_pending());
+            tokio::time::sleep(std::time::Duration::from_millis(1)).await;
+        }
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), 3);
+
+        // the second passes the offset check against what is on disk, but must not
+        // interleave with the first
+        let response = call_service(&app, patch(&location, 3, b"XX").to_request()).await;
+        assert_eq!(response.status(), StatusCode::LOCKED);
+        assert_eq!(header(&response, "tus-resumable"), TUS_VERSION);
+        let response = call_service(&app, request(Method::DELETE, &location).to_request()).await;
+        assert_eq!(response.status(), StatusCode::LOCKED);
+
+        sender
+            .unbounded_send(Ok(Bytes::from_static(b"lo")))
+            .unwrap();
+        drop(sender);
+        let response = slow.await;
+        assert_eq!(header(&response, "upload-offset"), "5");
+
+        let response = call_service(&app, patch(&location, 5, b"world!").to_request()).await;
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(header(&response, "tus-resumable"), TUS_VERSION);
+
+        let response = call_service(&app, patch(&location, 5, b"world").to_request()).await;
+        assert_eq!(header(&response, "upload-offset"), "10");
+        assert_eq!(std::fs::read(&path).unwrap(), b"helloworld");
+    }
+
+    #[ntex::test]
+    async fn test_creation_is_validated() {
+        let tus = server("validation");
+        let app =
+            init_service(App::new().service(web::scope("/files").configure(tus.configure()))).await;
+
+        let too_large = request(Method::POST, "/files")
+            .header("upload-length", "17")
+            .header("upload-metadata", "filetype dmlkZW8vbXA0")
+            .to_request();
+        let response = call_service(&app, too_large).await;
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+        let wrong_type = request(Method::POST, "/files")
+            .header("upload-length", "4")
+            .header("upload-metadata", "filetype aW1hZ2UvcG5n")
+            .to_request();
+        let response = call_service(&app, wrong_type).await;
+        assert_eq!(response.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+        let old_client = TestRequest::default()
+            .method(Method::POST)
+            .uri("/files")
+            .header("tus-resumable", "0.2.2")
+            .header("upload-length", "4")
+            .to_request();
+        let response = call_service(&app, old_client).await;
+        assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+    }
+}
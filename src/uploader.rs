@@ -1,30 +1,112 @@
+use std::collections::HashMap;
 use std::convert::Infallible;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
-use futures::StreamExt;
-use ntex::http::Payload;
+use ntex::http::header::CONTENT_LENGTH;
+use ntex::http::{HeaderMap, Payload};
 use ntex::util::Bytes;
 use ntex::web::{FromRequest, HttpRequest};
-use ntex_multipart::Multipart as NtexMultipart;
-use tokio::fs::File;
-use tokio::io::AsyncWriteExt;
+use ntex_multipart::{Field, Multipart as NtexMultipart};
+use tokio::io::AsyncWrite;
+use tokio::sync::watch;
 
+use crate::captured::CapturedFile;
+use crate::checksum::{digest_matches, ChecksumAlgorithm, Hasher};
+use crate::extension::validate_extension;
 use crate::file::FileInfo;
+use crate::image::{ImageConstraints, ImageProbe};
+use crate::metadata::MetadataStripper;
+use crate::mime::validate_mime;
+use crate::pipeline::{ImagePipeline, ImageProcessor};
+use crate::progress::{ProgressHook, UploadProgress};
 use crate::result::MultipartError::{NotUploaded, ValidationError};
-use crate::result::MultipartValidationError::{InvalidMimeType, LowerSizeError, UpperSizeError};
+use crate::result::MultipartValidationError::{
+    ChecksumMismatch, CombinedSizeError, HeaderSizeError, InvalidTextField, LowerSizeError,
+    TextFieldSizeError, TooFewFiles, TooManyFiles, TooManyParts, TotalSizeError,
+    TotalTextSizeError, UpperSizeError,
+};
 use crate::result::{MultipartError, MultipartResult};
+use crate::scan::Scanner;
+use crate::sink::{ChunkSink, WriteSink};
+use crate::sniff::{SniffMode, Sniffer};
+use crate::storage::{LocalStorage, StorageBackend, StorageSink, StorageWriter, StoredFile};
+use crate::streamed::StreamedFile;
+use crate::text::{TextFields, DEFAULT_TEXT_FIELD_LIMIT};
+use crate::timeout::{Clock, Timeouts};
 
 pub struct Uploader {
     multipart: NtexMultipart,
-    bytes: Vec<Bytes>,
-    file: FileInfo,
+    captured: CapturedFile,
+    text_fields: TextFields,
+    text_limits: HashMap<String, usize>,
+    default_text_limit: usize,
+    temp_dir: PathBuf,
+    limits: RequestLimits,
+    usage: Usage,
+    clock: Clock,
+    content_length: Option<usize>,
+    progress: Vec<ProgressHook>,
 }
 
+#[derive(Default, Clone)]
 pub struct UploadData<'a> {
     pub field: &'a str,
     pub lower_size: usize,
     pub upper_size: Option<usize>,
+    /// Accepted content types, supports wildcards like `image/*`; empty accepts any type
     pub allowed_mimes: Vec<&'a str>,
+    /// Rejected content types, checked after `allowed_mimes`
+    pub denied_mimes: Vec<&'a str>,
+    /// Accepted extensions such as `pdf` or `tar.gz`, compared case-insensitively with
+    /// [`FileInfo::inferred_extension`] once sniffing settled; empty accepts any file,
+    /// otherwise files without a known extension are rejected
+    pub allowed_extensions: Vec<&'a str>,
+    /// Whether the leading bytes are inspected to detect the real content type
+    pub sniff: SniffMode,
+    /// Digests computed while the field streams in, stored on [`FileInfo::checksums`]
+    pub checksums: Vec<ChecksumAlgorithm>,
+    /// Text field holding the client's digest of this file, sent before the file;
+    /// a part `Content-MD5` header is always verified when present
+    pub checksum_field: Option<(&'a str, ChecksumAlgorithm)>,
+    /// Validates the field as an image, decoding only its header while it streams in
+    pub image: Option<ImageConstraints>,
+    /// Removes EXIF, XMP and GPS metadata from JPEG, PNG and WebP files without
+    /// re-encoding them, before the bytes reach memory, disk or storage; a JPEG
+    /// keeps its EXIF orientation. A WebP is held in memory until complete, as its
+    /// header records the final size, so it is rejected unless `upper_size` is set.
+    pub strip_metadata: bool,
+}
+
+/// Limits that apply to the request as a whole rather than to a single field;
+/// `None` leaves the respective dimension unbounded
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RequestLimits {
+    /// Maximum number of parts, file and text fields alike
+    pub max_parts: Option<usize>,
+    /// Maximum number of body bytes across all parts
+    pub max_total_size: Option<usize>,
+    /// Maximum size of the headers of a single part; advisory, as ntex-multipart buffers
+    /// and parses a part's headers (at most 32 of them) before they can be checked
+    pub max_header_size: Option<usize>,
+    /// Maximum number of bytes across all text fields
+    pub max_text_size: Option<usize>,
+}
+
+/// Constraints on the files collected by [`Uploader::capture_many`]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct FileCountLimits {
+    pub min_count: usize,
+    pub max_count: Option<usize>,
+    /// Maximum combined size of all files sent for the field
+    pub max_total_size: Option<usize>,
+}
+
+/// What the request has consumed of its [`RequestLimits`] so far
+#[derive(Debug, Default)]
+struct Usage {
+    parts: usize,
+    total_size: usize,
+    text_size: usize,
 }
 
 impl<Err> FromRequest<Err> for Uploader {
@@ -34,8 +116,15 @@ impl<Err> FromRequest<Err> for Uploader {
         req: &HttpRequest,
         payload: &mut Payload,
     ) -> Result<Uploader, Infallible> {
+        let content_length = req
+            .headers()
+            .get(CONTENT_LENGTH)
+            .and_then(|len| len.to_str().ok()?.parse().ok());
+
         let multipart = NtexMultipart::new(req.headers(), payload.take());
-        Ok(Uploader::new(multipart).await)
+        let mut uploader = Uploader::new(multipart).await;
+        uploader.content_length = content_length;
+        Ok(uploader)
     }
 }
 
@@ -43,17 +132,81 @@ impl<'a> Uploader {
     pub async fn new(multipart: NtexMultipart) -> Uploader {
         Self {
             multipart,
-            bytes: vec![],
-            file: FileInfo::default(),
+            captured: CapturedFile::default(),
+            text_fields: TextFields::default(),
+            text_limits: HashMap::new(),
+            default_text_limit: DEFAULT_TEXT_FIELD_LIMIT,
+            temp_dir: std::env::temp_dir(),
+            limits: RequestLimits::default(),
+            usage: Usage::default(),
+            clock: Clock::default(),
+            content_length: None,
+            progress: vec![],
         }
     }
 
+    /// Sets limits enforced across all parts of the request
+    pub fn limits(&mut self, limits: RequestLimits) -> &mut Uploader {
+        self.limits = limits;
+        self
+    }
+
+    /// Sets idle, deadline and minimum rate timeouts enforced while reading the request
+    pub fn timeouts(&mut self, timeouts: Timeouts) -> &mut Uploader {
+        self.clock.timeouts = timeouts;
+        self
+    }
+
+    /// Sets the request body size progress percentages are computed against;
+    /// taken from the `Content-Length` header when extracted from a request
+    pub fn content_length(&mut self, content_length: usize) -> &mut Uploader {
+        self.content_length = Some(content_length);
+        self
+    }
+
+    /// Calls `callback` every time a chunk of a part arrives
+    pub fn on_progress<F>(&mut self, callback: F) -> &mut Uploader
+    where
+        F: FnMut(&UploadProgress) + 'static,
+    {
+        self.progress
+            .push(ProgressHook::Callback(Box::new(callback)));
+        self
+    }
+
+    /// Publishes progress to a channel, e.g. to forward it over a WebSocket or SSE
+    /// stream registered under the client's upload id
+    pub fn progress_channel(&mut self) -> watch::Receiver<UploadProgress> {
+        let (sender, receiver) = watch::channel(UploadProgress {
+            content_length: self.content_length,
+            ..UploadProgress::default()
+        });
+        self.progress.push(ProgressHook::Channel(sender));
+        receiver
+    }
+
+    /// Sets the directory streamed uploads are written to, defaults to the system temp dir
+    pub fn temp_dir<P: AsRef<Path>>(&mut self, dir: P) -> &mut Uploader {
+        self.temp_dir = dir.as_ref().to_path_buf();
+        self
+    }
+
+    /// Sets the maximum size in bytes accepted for the given text field
+    pub fn text_field_limit(&mut self, field: &str, upper_size: usize) -> &mut Uploader {
+        self.text_limits.insert(field.to_string(), upper_size);
+        self
+    }
+
+    /// Sets the maximum size in bytes accepted for text fields without an explicit limit
+    pub fn default_text_field_limit(&mut self, upper_size: usize) -> &mut Uploader {
+        self.default_text_limit = upper_size;
+        self
+    }
+
     pub async fn capture(&mut self, field: &str) -> Result<&mut Uploader, MultipartError> {
         self.capture_advance(UploadData {
             field,
-            lower_size: 0,
-            upper_size: None,
-            allowed_mimes: vec![],
+            ..UploadData::default()
         })
         .await
     }
@@ -62,67 +215,503 @@ impl<'a> Uploader {
         &mut self,
         ud: UploadData<'a>,
     ) -> Result<&mut Uploader, MultipartError> {
-        while let Some(item) = self.multipart.next().await {
-            let mut field = match item {
-                Ok(item) => item,
-                Err(err) => return Err(MultipartError::NtexError(err)),
-            };
-
-            let mut info = FileInfo::create(field.headers())?;
+        while let Some((mut field, info)) = self.next_file_field().await? {
             if info.field == ud.field {
-                if ud.allowed_mimes.contains(&&*info.content_type) {
-                    return Err(ValidationError(InvalidMimeType));
-                }
-
-                let mut total_size = 0;
                 let mut bytes: Vec<Bytes> = vec![];
-                while let Some(chunk) = field.next().await {
-                    let data = chunk.unwrap();
-                    total_size += data.len();
+                let info = self.read_field(&mut field, info, &ud, &mut bytes).await?;
+                self.captured = CapturedFile::new(info, bytes);
+                return Ok(self);
+            }
 
-                    if ud.upper_size.is_some() && total_size > ud.upper_size.unwrap() {
-                        return Err(ValidationError(UpperSizeError));
-                    }
+            self.skip_field(field, &info.field).await?;
+        }
 
-                    bytes.push(data);
+        Err(NotUploaded)
+    }
+
+    /// Captures every field described in `specs` in a single pass over the multipart stream,
+    /// regardless of the order in which the client sent them.
+    ///
+    /// Fields that were not sent are simply absent from the returned map, and every
+    /// text field in the request is made available through [`Uploader::text_fields`].
+    pub async fn capture_all(
+        &mut self,
+        specs: Vec<UploadData<'a>>,
+    ) -> MultipartResult<HashMap<String, CapturedFile>> {
+        let mut captured = HashMap::new();
+
+        while let Some((mut field, info)) = self.next_file_field().await? {
+            let spec = specs
+                .iter()
+                .find(|ud| ud.field == info.field && !captured.contains_key(ud.field));
+
+            match spec {
+                Some(ud) => {
+                    let mut bytes: Vec<Bytes> = vec![];
+                    let info = self.read_field(&mut field, info, ud, &mut bytes).await?;
+                    captured.insert(ud.field.to_string(), CapturedFile::new(info, bytes));
                 }
+                None => self.skip_field(field, &info.field).await?,
+            }
+        }
+
+        Ok(captured)
+    }
+
+    /// Captures every part sent for `ud.field`, as browsers do for `<input type="file" multiple>`;
+    /// each file is validated against `ud` and the files together against `limits`
+    pub async fn capture_many(
+        &mut self,
+        ud: UploadData<'a>,
+        limits: FileCountLimits,
+    ) -> MultipartResult<Vec<CapturedFile>> {
+        let mut files = vec![];
+        let mut total_size = 0;
+
+        while let Some((mut field, info)) = self.next_file_field().await? {
+            if info.field != ud.field {
+                self.skip_field(field, &info.field).await?;
+                continue;
+            }
+
+            if exceeds(files.len() + 1, limits.max_count) {
+                return Err(ValidationError(TooManyFiles));
+            }
+
+            // cap the file at what is left of the combined budget so it fails mid-stream
+            let remaining = limits.max_total_size.map(|max| max - total_size);
+            let upper_size = match (ud.upper_size, remaining) {
+                (Some(upper), Some(remaining)) => Some(upper.min(remaining)),
+                (upper, remaining) => upper.or(remaining),
+            };
+
+            let capped = UploadData {
+                upper_size,
+                ..ud.clone()
+            };
 
-                if total_size < ud.lower_size {
-                    return Err(ValidationError(LowerSizeError));
+            let mut bytes: Vec<Bytes> = vec![];
+            let info = match self.read_field(&mut field, info, &capped, &mut bytes).await {
+                Err(ValidationError(UpperSizeError)) if upper_size != ud.upper_size => {
+                    return Err(ValidationError(CombinedSizeError))
                 }
+                result => result?,
+            };
 
-                info.size = total_size;
-                self.bytes = bytes;
-                self.file = info;
+            total_size += info.size;
+            files.push(CapturedFile::new(info, bytes));
+        }
 
-                return Ok(self);
+        if files.len() < limits.min_count {
+            return Err(ValidationError(TooFewFiles));
+        }
+
+        Ok(files)
+    }
+
+    /// Captures every file part in the request, validating parts named in `specs`
+    /// against their spec; `fallback_upper_size` bounds parts without an `upper_size`
+    pub(crate) async fn capture_every(
+        &mut self,
+        specs: &[UploadData<'_>],
+        fallback_upper_size: Option<usize>,
+    ) -> MultipartResult<HashMap<String, Vec<CapturedFile>>> {
+        let mut captured: HashMap<String, Vec<CapturedFile>> = HashMap::new();
+
+        while let Some((mut field, info)) = self.next_file_field().await? {
+            let fallback = UploadData {
+                field: &info.field,
+                upper_size: fallback_upper_size,
+                ..UploadData::default()
+            };
+
+            let ud = match specs.iter().find(|ud| ud.field == info.field) {
+                Some(ud) => UploadData {
+                    upper_size: ud.upper_size.or(fallback_upper_size),
+                    ..ud.clone()
+                },
+                None => fallback,
+            };
+
+            let mut bytes: Vec<Bytes> = vec![];
+            let file = self
+                .read_field(&mut field, info.clone(), &ud, &mut bytes)
+                .await?;
+            captured
+                .entry(file.field.clone())
+                .or_default()
+                .push(CapturedFile::new(file, bytes));
+        }
+
+        Ok(captured)
+    }
+
+    /// Streams the field into `sink` chunk by chunk instead of buffering it in memory
+    pub async fn capture_to<W: AsyncWrite + Unpin>(
+        &mut self,
+        ud: UploadData<'a>,
+        sink: &mut W,
+    ) -> MultipartResult<FileInfo> {
+        while let Some((mut field, info)) = self.next_file_field().await? {
+            if info.field == ud.field {
+                return self
+                    .read_field(&mut field, info, &ud, &mut WriteSink(sink))
+                    .await;
             }
+
+            self.skip_field(field, &info.field).await?;
+        }
+
+        Err(NotUploaded)
+    }
+
+    /// Streams the field straight into `storage` under the key returned by `key`,
+    /// aborting the write if the stream fails or validation rejects the part
+    pub async fn capture_to_storage<S, K>(
+        &mut self,
+        ud: UploadData<'a>,
+        storage: &S,
+        key: K,
+    ) -> MultipartResult<StoredFile>
+    where
+        S: StorageBackend,
+        K: FnOnce(&FileInfo) -> String,
+    {
+        while let Some((mut field, info)) = self.next_file_field().await? {
+            if info.field == ud.field {
+                let key = key(&info);
+                let mut writer = storage.stream(&key, &info).await?;
+
+                let sink = &mut StorageSink(&mut writer);
+                return match self.read_field(&mut field, info, &ud, sink).await {
+                    Ok(file) => Ok(StoredFile { key, file }),
+                    Err(err) => {
+                        let _ = writer.abort().await;
+                        Err(err)
+                    }
+                };
+            }
+
+            self.skip_field(field, &info.field).await?;
+        }
+
+        Err(NotUploaded)
+    }
+
+    /// Streams the field into a temporary file inside [`Uploader::temp_dir`];
+    /// the file is removed again if validation fails mid-stream
+    pub async fn capture_to_temp(&mut self, ud: UploadData<'a>) -> MultipartResult<StreamedFile> {
+        while let Some((mut field, info)) = self.next_file_field().await? {
+            if info.field == ud.field {
+                let (mut streamed, mut file) = StreamedFile::create(&self.temp_dir).await?;
+                let sink = &mut WriteSink(&mut file);
+                streamed.file = self.read_field(&mut field, info, &ud, sink).await?;
+                return Ok(streamed);
+            }
+
+            self.skip_field(field, &info.field).await?;
         }
 
         Err(NotUploaded)
     }
 
     pub async fn save<P: AsRef<Path>>(&self, path: &P) -> MultipartResult<()> {
-        let mut file = File::create(path).await?;
+        self.captured.save(path).await
+    }
+
+    /// Saves the captured file below `root` under its content hash (`ab/cd/abcdef…`),
+    /// skipping the write when the same content was saved before; returns the path
+    pub async fn save_deduplicated<P: AsRef<Path>>(&self, root: &P) -> MultipartResult<PathBuf> {
+        let storage = LocalStorage::new(root);
+        let key = self.captured.store_deduplicated(&storage).await?;
+        storage.path(&key)
+    }
+
+    /// Checks the captured file for malware, see [`CapturedFile::scan`]
+    pub async fn scan<S: Scanner>(&self, scanner: &S) -> MultipartResult<()> {
+        self.captured.scan(scanner).await
+    }
+
+    /// Runs `pipeline` on the captured image, see [`CapturedFile::process`];
+    /// the derived variants are written alongside it by [`Uploader::save`]
+    pub fn process_image<P: ImageProcessor>(
+        &mut self,
+        pipeline: &ImagePipeline,
+        processor: &P,
+    ) -> MultipartResult<&mut Uploader> {
+        self.captured.process(pipeline, processor)?;
+        Ok(self)
+    }
+
+    pub fn file(&self) -> &FileInfo {
+        self.captured.file()
+    }
+
+    /// Text fields read so far; `capture_advance` stops at its field, so only
+    /// fields sent before it are available, while `capture_all` collects all of them
+    pub fn text_fields(&self) -> &TextFields {
+        &self.text_fields
+    }
+
+    async fn read_text_field(&mut self, field: &mut Field, name: String) -> MultipartResult<()> {
+        let limit = self
+            .text_limits
+            .get(&name)
+            .copied()
+            .unwrap_or(self.default_text_limit);
+
+        let mut value = vec![];
+        while let Some(chunk) = self.clock.next(field).await? {
+            let data = chunk.map_err(|source| MultipartError::Incomplete {
+                received: value.len(),
+                source,
+            })?;
+            if value.len() + data.len() > limit {
+                return Err(ValidationError(TextFieldSizeError));
+            }
+
+            self.usage.text_size += data.len();
+            if exceeds(self.usage.text_size, self.limits.max_text_size) {
+                return Err(ValidationError(TotalTextSizeError));
+            }
+
+            self.consume(data.len())?;
+            self.report(&name, value.len() + data.len());
 
-        for byte in &self.bytes {
-            file.write_all(byte).await?;
+            value.extend_from_slice(&data);
         }
 
-        file.flush().await?;
+        let value = String::from_utf8(value).map_err(|_| ValidationError(InvalidTextField))?;
+        self.text_fields.insert(name, value);
         Ok(())
     }
 
-    pub fn file(&self) -> &FileInfo {
-        &self.file
+    /// Advances to the next file part, collecting any text fields found on the way
+    async fn next_file_field(&mut self) -> MultipartResult<Option<(Field, FileInfo)>> {
+        while let Some(item) = self.clock.next(&mut self.multipart).await? {
+            let mut field = match item {
+                Ok(item) => item,
+                Err(err) => return Err(MultipartError::NtexError(err)),
+            };
+
+            self.usage.parts += 1;
+            if exceeds(self.usage.parts, self.limits.max_parts) {
+                return Err(ValidationError(TooManyParts));
+            }
+
+            if exceeds(header_size(field.headers()), self.limits.max_header_size) {
+                return Err(ValidationError(HeaderSizeError));
+            }
+
+            if let Some(name) = FileInfo::text_field_name(field.headers())? {
+                self.read_text_field(&mut field, name).await?;
+                continue;
+            }
+
+            let info = FileInfo::create(field.headers())?;
+            return Ok(Some((field, info)));
+        }
+
+        Ok(None)
     }
+
+    /// Reads and discards a part nobody asked for, so its bytes still count towards
+    /// the request limits, timeouts and progress instead of being drained unseen
+    async fn skip_field(&mut self, mut field: Field, name: &str) -> MultipartResult<()> {
+        let mut received = 0;
+        while let Some(chunk) = self.clock.next(&mut field).await? {
+            let data = chunk.map_err(|source| MultipartError::Incomplete { received, source })?;
+            received += data.len();
+            self.consume(data.len())?;
+            self.report(name, received);
+        }
+
+        Ok(())
+    }
+
+    /// Accounts `len` more body bytes against [`RequestLimits::max_total_size`]
+    /// and [`Timeouts::min_rate`]
+    fn consume(&mut self, len: usize) -> MultipartResult<()> {
+        self.usage.total_size += len;
+        self.clock.check_rate(self.usage.total_size)?;
+        match exceeds(self.usage.total_size, self.limits.max_total_size) {
+            true => Err(ValidationError(TotalSizeError)),
+            false => Ok(()),
+        }
+    }
+
+    fn report(&mut self, field: &str, field_bytes: usize) {
+        if self.progress.is_empty() {
+            return;
+        }
+
+        let progress = UploadProgress {
+            field: field.to_string(),
+            field_bytes,
+            received: self.usage.total_size,
+            content_length: self.content_length,
+        };
+        for hook in &mut self.progress {
+            hook.notify(&progress);
+        }
+    }
+
+    async fn read_field(
+        &mut self,
+        field: &mut Field,
+        mut info: FileInfo,
+        ud: &UploadData<'_>,
+        sink: &mut impl ChunkSink,
+    ) -> MultipartResult<FileInfo> {
+        validate_mime(&info.content_type, &ud.allowed_mimes, &ud.denied_mimes)?;
+        if ud.sniff == SniffMode::Disabled {
+            validate_extension(&info, &ud.allowed_extensions)?;
+        }
+
+        let mut sniffer = (ud.sniff != SniffMode::Disabled).then(Sniffer::default);
+        let mut probe = ud.image.as_ref().map(|_| ImageProbe::default());
+
+        let mut expected = vec![];
+        if let Some(md5) = field.headers().get("content-md5") {
+            let md5 = md5
+                .to_str()
+                .map_err(|_| ValidationError(ChecksumMismatch))?;
+            expected.push((ChecksumAlgorithm::Md5, md5.to_string()));
+        }
+
+        if let Some((name, algorithm)) = ud.checksum_field {
+            let digest = self.text_fields.get(name);
+            let digest = digest.ok_or(ValidationError(ChecksumMismatch))?;
+            expected.push((algorithm, digest.to_string()));
+        }
+
+        let algorithms: Vec<_> = ud
+            .checksums
+            .iter()
+            .chain(expected.iter().map(|(a, _)| a))
+            .collect();
+        let mut hasher = Hasher::new(algorithms.iter().copied());
+
+        // the stripped bytes are hashed and counted separately, as client digests
+        // cover the file as sent
+        let mut stripper = ud.strip_metadata.then(|| {
+            let hasher = Hasher::new(algorithms.iter().copied());
+            let stripper = MetadataStripper::new(ud.upper_size.is_some());
+            (stripper, hasher, 0, vec![])
+        });
+
+        let mut total_size = 0;
+        while let Some(chunk) = self.clock.next(field).await? {
+            let data = chunk.map_err(|source| MultipartError::Incomplete {
+                received: total_size,
+                source,
+            })?;
+            total_size += data.len();
+
+            if ud.upper_size.is_some() && total_size > ud.upper_size.unwrap() {
+                return Err(ValidationError(UpperSizeError));
+            }
+
+            self.consume(data.len())?;
+            self.report(&info.field, total_size);
+
+            if let Some(sniffer) = sniffer.as_mut() {
+                if sniffer.feed(&data) {
+                    sniffer.apply(&mut info, ud.sniff)?;
+                    validate_extension(&info, &ud.allowed_extensions)?;
+                }
+            }
+
+            if let (Some(probe), Some(constraints)) = (probe.as_mut(), &ud.image) {
+                if let Some(image) = probe.feed(&data)? {
+                    constraints.check(&image)?;
+                }
+            }
+
+            hasher.update(&data);
+            match stripper.as_mut() {
+                Some((stripper, hasher, size, out)) => {
+                    stripper.feed(data, out)?;
+                    for piece in out.drain(..) {
+                        hasher.update(&piece);
+                        *size += piece.len();
+                        sink.write_chunk(piece).await?;
+                    }
+                }
+                None => sink.write_chunk(data).await?,
+            }
+        }
+
+        if let Some(sniffer) = sniffer.as_mut().filter(|s| !s.is_done()) {
+            sniffer.apply(&mut info, ud.sniff)?;
+            validate_extension(&info, &ud.allowed_extensions)?;
+        }
+
+        if let Some(probe) = probe {
+            info.image = Some(probe.finish()?);
+        }
+
+        if total_size < ud.lower_size {
+            return Err(ValidationError(LowerSizeError));
+        }
+
+        info.checksums = hasher.finish();
+        for (algorithm, digest) in &expected {
+            let actual = info.checksums.get(*algorithm).unwrap_or_default();
+            if !digest_matches(digest, actual) {
+                return Err(ValidationError(ChecksumMismatch));
+            }
+        }
+
+        info.size = total_size;
+        if let Some((mut stripper, mut hasher, mut size, mut out)) = stripper {
+            stripper.finish(&mut out)?;
+            for piece in out {
+                hasher.update(&piece);
+                size += piece.len();
+                sink.write_chunk(piece).await?;
+            }
+            info.checksums = hasher.finish();
+            info.size = size;
+        }
+
+        sink.finish().await?;
+        Ok(info)
+    }
+}
+
+fn exceeds(value: usize, limit: Option<usize>) -> bool {
+    limit.is_some_and(|limit| value > limit)
+}
+
+/// Approximate size of the part headers as sent, counting `name: value\r\n` per header
+fn header_size(headers: &HeaderMap) -> usize {
+    headers
+        .iter()
+        .map(|(name, value)| name.as_str().len() + value.len() + 4)
+        .sum()
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
+    use std::time::Duration;
+
+    use futures::{stream, Stream, StreamExt};
+    use ntex::http::error::PayloadError;
     use ntex::http::HeaderMap;
+    use ntex::util::Bytes;
+    use ntex_multipart::Multipart as NtexMultipart;
 
+    use crate::checksum::ChecksumAlgorithm;
     use crate::file::FileInfo;
+    use crate::image::ImageConstraints;
+    use crate::result::{MultipartError, MultipartValidationError};
+    use crate::sniff::SniffMode;
+    use crate::storage::MemoryStorage;
+    use crate::timeout::Timeouts;
+    use crate::uploader::{FileCountLimits, RequestLimits, UploadData, Uploader};
+
+    const BOUNDARY: &str = "medullah-boundary";
 
     #[tokio::test]
     async fn test_file_info_create() {
@@ -153,4 +742,811 @@ mod tests {
         );
         headers
     }
+
+    #[tokio::test]
+    async fn test_capture_all_in_any_order() {
+        let mut uploader = generate_uploader(&[
+            ("cover", "cover.png", "image/png", b"cover-bytes"),
+            ("ignored", "ignored.txt", "text/plain", b"ignored"),
+            ("avatar", "avatar.png", "image/png", b"avatar-bytes"),
+        ])
+        .await;
+
+        let files = uploader
+            .capture_all(vec![upload_data("avatar"), upload_data("cover")])
+            .await
+            .unwrap();
+
+        assert_eq!(files.len(), 2);
+        assert_eq!(files["avatar"].file().name, "avatar.png");
+        assert_eq!(files["avatar"].file().size, 12);
+        assert_eq!(files["cover"].file().name, "cover.png");
+        assert_eq!(files["cover"].bytes().concat(), b"cover-bytes");
+    }
+
+    #[tokio::test]
+    async fn test_capture_all_omits_missing_fields() {
+        let mut uploader =
+            generate_uploader(&[("avatar", "avatar.png", "image/png", b"avatar")]).await;
+
+        let files = uploader
+            .capture_all(vec![upload_data("avatar"), upload_data("cover")])
+            .await
+            .unwrap();
+
+        assert!(files.contains_key("avatar"));
+        assert!(!files.contains_key("cover"));
+    }
+
+    #[tokio::test]
+    async fn test_text_fields_next_to_files() {
+        let mut uploader = generate_form_uploader(&[
+            Part::Text("title", "Holiday"),
+            Part::File("avatar", "avatar.png", "image/png", b"avatar"),
+            Part::Text("count", "3"),
+        ])
+        .await;
+
+        let files = uploader
+            .capture_all(vec![upload_data("avatar")])
+            .await
+            .unwrap();
+
+        assert!(files.contains_key("avatar"));
+        assert_eq!(uploader.text_fields().get("title"), Some("Holiday"));
+        assert_eq!(
+            uploader.text_fields().parse::<u8>("count").unwrap(),
+            Some(3)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_text_field_limit() {
+        let mut uploader = generate_form_uploader(&[
+            Part::Text("title", "A rather long title"),
+            Part::File("avatar", "avatar.png", "image/png", b"avatar"),
+        ])
+        .await;
+
+        uploader.text_field_limit("title", 5);
+        assert!(matches!(
+            uploader.capture("avatar").await,
+            Err(MultipartError::ValidationError(
+                MultipartValidationError::TextFieldSizeError
+            ))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_request_limits() {
+        let parts = [
+            Part::Text("title", "hello"),
+            Part::Text("tags", "a,b,c"),
+            Part::File("avatar", "avatar.png", "image/png", b"avatar"),
+        ];
+
+        let cases = [
+            (
+                RequestLimits {
+                    max_parts: Some(2),
+                    ..RequestLimits::default()
+                },
+                "too_many_parts",
+            ),
+            (
+                RequestLimits {
+                    max_total_size: Some(12),
+                    ..RequestLimits::default()
+                },
+                "request_too_large",
+            ),
+            (
+                RequestLimits {
+                    max_header_size: Some(32),
+                    ..RequestLimits::default()
+                },
+                "part_headers_too_large",
+            ),
+            (
+                RequestLimits {
+                    max_text_size: Some(8),
+                    ..RequestLimits::default()
+                },
+                "text_fields_too_large",
+            ),
+        ];
+
+        for (limits, code) in cases {
+            let mut uploader = generate_form_uploader(&parts).await;
+            uploader.limits(limits);
+            let err = uploader.capture("avatar").await.err().unwrap();
+            assert_eq!(err.code(), code);
+        }
+
+        let mut uploader = generate_form_uploader(&parts).await;
+        uploader.limits(RequestLimits {
+            max_parts: Some(3),
+            max_total_size: Some(16),
+            max_header_size: Some(256),
+            max_text_size: Some(10),
+        });
+        assert!(uploader.capture("avatar").await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_request_limits_count_skipped_parts() {
+        let padding = vec![0; 1024];
+        let parts = [
+            Part::File(
+                "padding",
+                "padding.bin",
+                "application/octet-stream",
+                &padding,
+            ),
+            Part::File("avatar", "avatar.png", "image/png", b"avatar"),
+        ];
+
+        let mut uploader = generate_form_uploader(&parts).await;
+        uploader.limits(RequestLimits {
+            max_total_size: Some(512),
+            ..RequestLimits::default()
+        });
+        let err = uploader.capture("avatar").await.err().unwrap();
+        assert_eq!(err.code(), "request_too_large");
+
+        // skipped parts count towards progress as well
+        let received = std::rc::Rc::new(std::cell::Cell::new(0));
+        let reported = received.clone();
+        let mut uploader = generate_form_uploader(&parts).await;
+        uploader.on_progress(move |progress| reported.set(progress.received));
+        uploader.capture("avatar").await.unwrap();
+        assert_eq!(received.get(), 1024 + 6);
+    }
+
+    #[tokio::test]
+    async fn test_capture_many() {
+        let parts = [
+            Part::File("photos", "a.png", "image/png", b"aaaa"),
+            Part::File("avatar", "avatar.png", "image/png", b"avatar"),
+            Part::File("photos", "b.png", "image/png", b"bbbb"),
+            Part::File("photos", "c.png", "image/png", b"cccc"),
+        ];
+
+        let mut uploader = generate_form_uploader(&parts).await;
+        let files = uploader
+            .capture_many(upload_data("photos"), FileCountLimits::default())
+            .await
+            .unwrap();
+        let names: Vec<&str> = files.iter().map(|f| f.file().name.as_str()).collect();
+        assert_eq!(names, ["a.png", "b.png", "c.png"]);
+
+        let cases = [
+            (
+                FileCountLimits {
+                    min_count: 4,
+                    ..FileCountLimits::default()
+                },
+                "too_few_files",
+            ),
+            (
+                FileCountLimits {
+                    max_count: Some(2),
+                    ..FileCountLimits::default()
+                },
+                "too_many_files",
+            ),
+            (
+                FileCountLimits {
+                    max_total_size: Some(10),
+                    ..FileCountLimits::default()
+                },
+                "files_too_large",
+            ),
+        ];
+
+        for (limits, code) in cases {
+            let mut uploader = generate_form_uploader(&parts).await;
+            let err = uploader
+                .capture_many(upload_data("photos"), limits)
+                .await
+                .unwrap_err();
+            assert_eq!(err.code(), code);
+        }
+
+        let mut uploader = generate_form_uploader(&parts).await;
+        let mut ud = upload_data("photos");
+        ud.upper_size = Some(3);
+        let err = uploader
+            .capture_many(ud, FileCountLimits::default())
+            .await
+            .unwrap_err();
+        assert_eq!(err.code(), "file_too_large");
+    }
+
+    #[tokio::test]
+    async fn test_image_constraints() {
+        let mut png = b"\x89PNG\r\n\x1a\n\x00\x00\x00\x0dIHDR".to_vec();
+        png.extend_from_slice(&20000u32.to_be_bytes());
+        png.extend_from_slice(&20000u32.to_be_bytes());
+
+        let mut uploader = generate_uploader(&[
+            ("avatar", "avatar.png", "image/png", &png),
+            ("resume", "resume.pdf", "application/pdf", b"%PDF-1.7"),
+        ])
+        .await;
+
+        let mut avatar = upload_data("avatar");
+        avatar.image = Some(ImageConstraints {
+            max_width: Some(1024),
+            max_height: Some(1024),
+            ..ImageConstraints::default()
+        });
+        assert!(matches!(
+            uploader.capture_advance(avatar).await,
+            Err(MultipartError::ValidationError(
+                MultipartValidationError::ImageDimensionError {
+                    width: 20000,
+                    height: 20000
+                }
+            ))
+        ));
+
+        let mut uploader = generate_uploader(&[("avatar", "avatar.png", "image/png", &png)]).await;
+        let mut avatar = upload_data("avatar");
+        avatar.image = Some(ImageConstraints::default());
+        let image = uploader.capture_advance(avatar).await.unwrap().file().image;
+        assert_eq!(image.map(|i| i.width), Some(20000));
+
+        let mut uploader =
+            generate_uploader(&[("resume", "resume.pdf", "image/png", b"%PDF-1.7")]).await;
+        let mut resume = upload_data("resume");
+        resume.image = Some(ImageConstraints::default());
+        assert!(matches!(
+            uploader.capture_advance(resume).await,
+            Err(MultipartError::ValidationError(
+                MultipartValidationError::InvalidImage
+            ))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_strip_metadata() {
+        let jpeg = b"\xff\xd8\xff\xe1\x00\x0cExif\x00\x00GPS!\xff\xda\x00\x02pixels\xff\xd9";
+        let mut uploader = generate_uploader(&[("photo", "photo.jpg", "image/jpeg", jpeg)]).await;
+
+        let mut photo = upload_data("photo");
+        photo.strip_metadata = true;
+        photo.checksums = vec![ChecksumAlgorithm::Md5];
+
+        let captured = uploader.capture_all(vec![photo]).await.unwrap();
+        let photo = &captured["photo"];
+        assert_eq!(
+            photo.bytes().concat(),
+            b"\xff\xd8\xff\xda\x00\x02pixels\xff\xd9"
+        );
+        assert_eq!(photo.file().size, 14);
+        assert_eq!(
+            photo.file().checksums.md5.as_deref(),
+            Some("be7267b656735bec6fd9b71141e1a206")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_capture_to_sink() {
+        let mut uploader =
+            generate_uploader(&[("video", "clip.mp4", "video/mp4", b"frames")]).await;
+
+        let mut sink: Vec<u8> = vec![];
+        let info = uploader
+            .capture_to(upload_data("video"), &mut sink)
+            .await
+            .unwrap();
+
+        assert_eq!(info.size, 6);
+        assert_eq!(sink, b"frames");
+    }
+
+    #[tokio::test]
+    async fn test_capture_to_temp() {
+        let mut uploader =
+            generate_uploader(&[("video", "clip.mp4", "video/mp4", b"frames")]).await;
+
+        let streamed = uploader
+            .capture_to_temp(upload_data("video"))
+            .await
+            .unwrap();
+        let path = streamed.path().to_path_buf();
+
+        assert_eq!(streamed.file().name, "clip.mp4");
+        assert_eq!(std::fs::read(&path).unwrap(), b"frames");
+
+        drop(streamed);
+        assert!(!path.exists());
+    }
+
+    #[tokio::test]
+    async fn test_capture_to_temp_enforces_upper_size() {
+        let dir = std::env::temp_dir().join("medullah-upper-size-test");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let mut uploader =
+            generate_uploader(&[("video", "clip.mp4", "video/mp4", b"frames")]).await;
+        uploader.temp_dir(&dir);
+
+        let mut ud = upload_data("video");
+        ud.upper_size = Some(3);
+        assert!(matches!(
+            uploader.capture_to_temp(ud).await,
+            Err(MultipartError::ValidationError(
+                MultipartValidationError::UpperSizeError
+            ))
+        ));
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_allowed_mimes() {
+        let mut uploader = generate_uploader(&[
+            ("avatar", "avatar.png", "image/png", b"png"),
+            ("doc", "doc.pdf", "application/pdf", b"pdf"),
+        ])
+        .await;
+
+        let mut avatar = upload_data("avatar");
+        avatar.allowed_mimes = vec!["image/*"];
+        let mut doc = upload_data("doc");
+        doc.allowed_mimes = vec!["image/*"];
+
+        assert!(uploader.capture_advance(avatar).await.is_ok());
+        assert!(matches!(
+            uploader.capture_advance(doc).await,
+            Err(MultipartError::ValidationError(
+                MultipartValidationError::InvalidMimeType
+            ))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_allowed_extensions() {
+        let mut uploader = generate_uploader(&[
+            ("backup", "site.TAR.GZ", "application/gzip", b"gz"),
+            ("doc", "invoice.pdf.php", "application/pdf", b"pdf"),
+            (
+                "scan",
+                "README",
+                "application/octet-stream",
+                b"\x89PNG\r\n\x1a\n....",
+            ),
+            ("logo", "logo.png", "image/png", b"%PDF-1.7"),
+        ])
+        .await;
+
+        let mut backup = upload_data("backup");
+        backup.allowed_extensions = vec!["tar.gz", "zip"];
+        let mut doc = upload_data("doc");
+        doc.allowed_extensions = vec!["pdf"];
+
+        uploader.capture_advance(backup).await.unwrap();
+        assert_eq!(uploader.file().extension.as_deref(), Some("tar.gz"));
+        assert!(matches!(
+            uploader.capture_advance(doc).await,
+            Err(MultipartError::ValidationError(
+                MultipartValidationError::ExtensionError
+            ))
+        ));
+
+        // with sniffing, the extension the content implies is checked
+        let mut scan = upload_data("scan");
+        scan.allowed_extensions = vec!["png"];
+        scan.sniff = SniffMode::Detect;
+        uploader.capture_advance(scan).await.unwrap();
+        assert_eq!(uploader.file().inferred_extension().as_deref(), Some("png"));
+
+        let mut logo = upload_data("logo");
+        logo.allowed_extensions = vec!["png"];
+        logo.sniff = SniffMode::Detect;
+        assert!(matches!(
+            uploader.capture_advance(logo).await,
+            Err(MultipartError::ValidationError(
+                MultipartValidationError::ExtensionError
+            ))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_sniff_rejects_spoofed_content_type() {
+        let mut uploader = generate_uploader(&[
+            (
+                "photo",
+                "photo.png",
+                "image/png",
+                b"\x89PNG\r\n\x1a\n-pixels",
+            ),
+            (
+                "avatar",
+                "avatar.png",
+                "image/png",
+                b"MZ\x90\x00-not-an-image",
+            ),
+        ])
+        .await;
+
+        let mut photo = upload_data("photo");
+        photo.sniff = SniffMode::Enforce;
+        let file = uploader.capture_advance(photo).await.unwrap().file();
+        assert_eq!(file.detected_content_type.as_deref(), Some("image/png"));
+
+        let mut avatar = upload_data("avatar");
+        avatar.sniff = SniffMode::Enforce;
+        assert!(matches!(
+            uploader.capture_advance(avatar).await,
+            Err(MultipartError::ValidationError(
+                MultipartValidationError::ContentTypeMismatch
+            ))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_truncated_body() {
+        let body = format!(
+            "--{}\r\nContent-Disposition: form-data; name=\"video\"; filename=\"clip.mp4\"\r\nContent-Type: video/mp4\r\n\r\npartial-frames",
+            BOUNDARY
+        );
+        let mut uploader = uploader_from_stream(vec![Ok(Bytes::from(body))]).await;
+
+        assert!(matches!(
+            uploader.capture("video").await,
+            Err(MultipartError::Incomplete { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_client_disconnect_mid_field() {
+        let head = format!(
+            "--{}\r\nContent-Disposition: form-data; name=\"video\"; filename=\"clip.mp4\"\r\nContent-Type: video/mp4\r\n\r\n",
+            BOUNDARY
+        );
+        let mut uploader = uploader_from_stream(vec![
+            Ok(Bytes::from(head)),
+            Ok(Bytes::from_static(&[0; 256])),
+            Err(PayloadError::Incomplete(None)),
+        ])
+        .await;
+
+        match uploader.capture("video").await {
+            Err(MultipartError::Incomplete { received, .. }) => assert!(received <= 256),
+            _ => unreachable!("expected an incomplete upload"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_progress_reporting() {
+        let head = format!(
+            "--{}\r\nContent-Disposition: form-data; name=\"video\"; filename=\"clip.mp4\"\r\nContent-Type: video/mp4\r\n\r\n",
+            BOUNDARY
+        );
+        let mut uploader = uploader_from_stream(vec![
+            Ok(Bytes::from(head)),
+            Ok(Bytes::from_static(&[1; 100])),
+            Ok(Bytes::from_static(&[2; 100])),
+            Ok(Bytes::from(format!("\r\n--{}--\r\n", BOUNDARY))),
+        ])
+        .await;
+
+        let reports = std::rc::Rc::new(std::cell::RefCell::new(vec![]));
+        let collected = reports.clone();
+        uploader
+            .content_length(400)
+            .on_progress(move |progress| collected.borrow_mut().push(progress.clone()));
+        let receiver = uploader.progress_channel();
+
+        uploader.capture("video").await.unwrap();
+
+        let reports = reports.borrow();
+        assert!(reports.len() >= 2);
+        assert!(reports
+            .windows(2)
+            .all(|w| w[0].field_bytes <= w[1].field_bytes));
+
+        let last = reports.last().unwrap();
+        assert_eq!(last.field, "video");
+        assert_eq!(last.field_bytes, 200);
+        assert_eq!(last.percentage(), Some(50.0));
+        assert_eq!(*receiver.borrow(), *last);
+    }
+
+    #[tokio::test]
+    async fn test_timeouts() {
+        let head = Bytes::from(format!(
+            "--{}\r\nContent-Disposition: form-data; name=\"video\"; filename=\"clip.mp4\"\r\nContent-Type: video/mp4\r\n\r\n",
+            BOUNDARY
+        ));
+        let tail = Bytes::from(format!("\r\n--{}--\r\n", BOUNDARY));
+        let trickle = || {
+            vec![
+                (0, head.clone()),
+                (0, Bytes::from_static(b"frame")),
+                (60, Bytes::from_static(b"frame")),
+                (60, Bytes::from_static(b"frame")),
+                (0, tail.clone()),
+            ]
+        };
+
+        let mut uploader = uploader_from_timed(trickle()).await;
+        uploader.timeouts(Timeouts {
+            idle: Some(Duration::from_millis(30)),
+            ..Timeouts::default()
+        });
+        assert!(matches!(
+            uploader.capture("video").await,
+            Err(MultipartError::IdleTimeout)
+        ));
+
+        let mut uploader = uploader_from_timed(trickle()).await;
+        uploader.timeouts(Timeouts {
+            idle: Some(Duration::from_millis(500)),
+            deadline: Some(Duration::from_millis(90)),
+            ..Timeouts::default()
+        });
+        assert!(matches!(
+            uploader.capture("video").await,
+            Err(MultipartError::DeadlineExceeded)
+        ));
+
+        let mut uploader = uploader_from_timed(trickle()).await;
+        uploader.timeouts(Timeouts {
+            min_rate: Some(1024),
+            rate_grace_period: Duration::from_millis(50),
+            ..Timeouts::default()
+        });
+        assert!(matches!(
+            uploader.capture("video").await,
+            Err(MultipartError::TooSlow { .. })
+        ));
+
+        // a stalled client is too slow even without an idle timeout
+        let mut stalled = trickle();
+        stalled[2].0 = 60_000;
+        let mut uploader = uploader_from_timed(stalled).await;
+        uploader.timeouts(Timeouts {
+            min_rate: Some(1024),
+            rate_grace_period: Duration::from_millis(50),
+            ..Timeouts::default()
+        });
+        assert!(matches!(
+            tokio::time::timeout(Duration::from_secs(5), uploader.capture("video")).await,
+            Ok(Err(MultipartError::TooSlow { .. }))
+        ));
+
+        let mut uploader = uploader_from_timed(trickle()).await;
+        uploader.timeouts(Timeouts {
+            idle: Some(Duration::from_millis(500)),
+            deadline: Some(Duration::from_secs(5)),
+            min_rate: Some(1),
+            rate_grace_period: Duration::from_millis(50),
+        });
+        uploader.capture("video").await.unwrap();
+        assert_eq!(uploader.file().size, 15);
+    }
+
+    #[tokio::test]
+    async fn test_save_deduplicated() {
+        let root = std::env::temp_dir().join("medullah-dedup");
+        let _ = std::fs::remove_dir_all(&root);
+
+        let mut uploader = generate_uploader(&[
+            ("a", "a.txt", "text/plain", b"abc"),
+            ("b", "b.txt", "text/plain", b"abc"),
+        ])
+        .await;
+
+        uploader.capture("a").await.unwrap();
+        let first = uploader.save_deduplicated(&root).await.unwrap();
+        uploader.capture("b").await.unwrap();
+        let second = uploader.save_deduplicated(&root).await.unwrap();
+
+        assert_eq!(first, second);
+        assert!(first
+            .ends_with("ba/78/ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"));
+        assert_eq!(std::fs::read(&first).unwrap(), b"abc");
+    }
+
+    #[tokio::test]
+    async fn test_capture_to_storage() {
+        let mut uploader =
+            generate_uploader(&[("doc", "report.pdf", "application/pdf", b"%PDF-1.7")]).await;
+        let storage = MemoryStorage::new();
+
+        let stored = uploader
+            .capture_to_storage(upload_data("doc"), &storage, |info| {
+                format!("docs/{}", info.sanitized_name())
+            })
+            .await
+            .unwrap();
+
+        assert_eq!(stored.key, "docs/report.pdf");
+        assert_eq!(stored.file.size, 8);
+        assert_eq!(storage.get("docs/report.pdf").unwrap(), b"%PDF-1.7");
+    }
+
+    #[tokio::test]
+    async fn test_capture_to_storage_strips_metadata() {
+        let jpeg = b"\xff\xd8\xff\xe1\x00\x0cExif\x00\x00GPS!\xff\xda\x00\x02pixels\xff\xd9";
+        let mut uploader = generate_uploader(&[("photo", "photo.jpg", "image/jpeg", jpeg)]).await;
+        let storage = MemoryStorage::new();
+
+        let mut photo = upload_data("photo");
+        photo.strip_metadata = true;
+        photo.checksums = vec![ChecksumAlgorithm::Md5];
+        let stored = uploader
+            .capture_to_storage(photo, &storage, |_| "photo.jpg".to_string())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            storage.get("photo.jpg").unwrap(),
+            b"\xff\xd8\xff\xda\x00\x02pixels\xff\xd9"
+        );
+        assert_eq!(stored.file.size, 14);
+        assert_eq!(
+            stored.file.checksums.md5.as_deref(),
+            Some("be7267b656735bec6fd9b71141e1a206")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_capture_to_storage_aborts_on_validation_error() {
+        let mut uploader =
+            generate_uploader(&[("doc", "report.pdf", "application/pdf", b"%PDF-1.7")]).await;
+        let storage = MemoryStorage::new();
+
+        let mut ud = upload_data("doc");
+        ud.upper_size = Some(4);
+        let result = uploader
+            .capture_to_storage(ud, &storage, |info| info.storage_name())
+            .await;
+
+        assert!(result.is_err());
+        assert!(storage.keys().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_capture_to_storage_rejects_small_files() {
+        let mut uploader =
+            generate_uploader(&[("doc", "report.pdf", "application/pdf", b"%PDF-1.7")]).await;
+        let storage = MemoryStorage::new();
+
+        let mut ud = upload_data("doc");
+        ud.lower_size = 100;
+        let result = uploader
+            .capture_to_storage(ud, &storage, |info| info.storage_name())
+            .await;
+
+        assert!(result.is_err());
+        assert!(storage.keys().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_checksums_and_digest_field() {
+        let mut uploader = generate_form_uploader(&[
+            Part::Text(
+                "sha256",
+                "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+            ),
+            Part::File("doc", "abc.txt", "text/plain", b"abc"),
+            Part::Text("md5", "00000000000000000000000000000000"),
+            Part::File("other", "abc.txt", "text/plain", b"abc"),
+        ])
+        .await;
+
+        let mut doc = upload_data("doc");
+        doc.checksums = vec![ChecksumAlgorithm::Md5, ChecksumAlgorithm::Crc32c];
+        doc.checksum_field = Some(("sha256", ChecksumAlgorithm::Sha256));
+
+        let checksums = &uploader
+            .capture_advance(doc)
+            .await
+            .unwrap()
+            .file()
+            .checksums;
+        assert_eq!(
+            checksums.md5.as_deref(),
+            Some("900150983cd24fb0d6963f7d28e17f72")
+        );
+        assert_eq!(checksums.crc32c.as_deref(), Some("364b3fb7"));
+        assert!(checksums.sha256.is_some());
+
+        let mut other = upload_data("other");
+        other.checksum_field = Some(("md5", ChecksumAlgorithm::Md5));
+        assert!(matches!(
+            uploader.capture_advance(other).await,
+            Err(MultipartError::ValidationError(
+                MultipartValidationError::ChecksumMismatch
+            ))
+        ));
+    }
+
+    fn upload_data(field: &str) -> UploadData<'_> {
+        UploadData {
+            field,
+            ..UploadData::default()
+        }
+    }
+
+    pub(crate) enum Part<'a> {
+        File(&'a str, &'a str, &'a str, &'a [u8]),
+        Text(&'a str, &'a str),
+    }
+
+    async fn generate_uploader(parts: &[(&str, &str, &str, &[u8])]) -> Uploader {
+        let parts: Vec<Part> = parts
+            .iter()
+            .map(|(field, filename, content_type, data)| {
+                Part::File(field, filename, content_type, data)
+            })
+            .collect();
+
+        generate_form_uploader(&parts).await
+    }
+
+    pub(crate) async fn generate_form_uploader(parts: &[Part<'_>]) -> Uploader {
+        let mut body = vec![];
+        for part in parts {
+            let (headers, data) = match part {
+                Part::File(field, filename, content_type, data) => (
+                    format!(
+                        "Content-Disposition: form-data; name=\"{}\"; filename=\"{}\"\r\nContent-Type: {}",
+                        field, filename, content_type
+                    ),
+                    *data,
+                ),
+                Part::Text(field, value) => (
+                    format!("Content-Disposition: form-data; name=\"{}\"", field),
+                    value.as_bytes(),
+                ),
+            };
+
+            body.extend_from_slice(format!("--{}\r\n{}\r\n\r\n", BOUNDARY, headers).as_bytes());
+            body.extend_from_slice(data);
+            body.extend_from_slice(b"\r\n");
+        }
+        body.extend_from_slice(format!("--{}--\r\n", BOUNDARY).as_bytes());
+
+        uploader_from_stream(vec![Ok(Bytes::from(body))]).await
+    }
+
+    async fn uploader_from_stream(chunks: Vec<Result<Bytes, PayloadError>>) -> Uploader {
+        // yield between chunks so errors surface while a field is being read, like on a socket
+        let payload = stream::iter(chunks).then(|chunk| async {
+            tokio::task::yield_now().await;
+            chunk
+        });
+
+        uploader_from_payload(payload).await
+    }
+
+    /// Sends each chunk after waiting the paired number of milliseconds
+    async fn uploader_from_timed(chunks: Vec<(u64, Bytes)>) -> Uploader {
+        let payload = stream::iter(chunks).then(|(delay, chunk)| async move {
+            tokio::time::sleep(Duration::from_millis(delay)).await;
+            Ok(chunk)
+        });
+
+        uploader_from_payload(payload).await
+    }
+
+    async fn uploader_from_payload(
+        payload: impl Stream<Item = Result<Bytes, PayloadError>> + 'static,
+    ) -> Uploader {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "content-type".parse().unwrap(),
+            format!("multipart/form-data; boundary={}", BOUNDARY)
+                .parse()
+                .unwrap(),
+        );
+
+        Uploader::new(NtexMultipart::new(&headers, Box::pin(payload))).await
+    }
 }